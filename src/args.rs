@@ -5,13 +5,58 @@
 // GNU Public License, version 2 only.
 // See COPYING.txt.
 
+use std::path::PathBuf;
+
+use crate::{
+    encoding::Dither,
+    frame_sink::{CoverageLayout, Orientation},
+    gui::ColorMode,
+    timing::DisplayTiming,
+    waveform::{Waveform, parse_waveform},
+};
+
 #[derive(clap::Parser)]
 pub struct Args {
     #[clap(verbatim_doc_comment)]
-    /// The product of the native resolution's width
+    /// The product of the native resolution's height
     /// and the monitor's refresh rate (in Hz),
     /// as explained in README.md.
-    pub horiz_refresh_rate: f64,
+    /// If neither this, --modeline, --timing nor --edid are given,
+    /// it's calculated from the current display mode.
+    /// With several displays, give it once for each of them, in order.
+    #[arg(short = 'r', long, conflicts_with_all = ["modeline", "timing", "edid"])]
+    pub horiz_refresh_rate: Vec<f64>,
+
+    #[clap(verbatim_doc_comment)]
+    /// The full timing of the current display mode, as an X11 modeline
+    /// (e.g. as printed by `cvt`), which, unlike the horizontal
+    /// refresh rate, accounts for the rows in the vertical blanking interval.
+    /// Example:
+    /// --modeline '"1366x768" 72.40 1366 1414 1446 1526 768 771 777 790'
+    /// With several displays, give it once for each of them, in order.
+    #[arg(long, value_parser = DisplayTiming::from_modeline, conflicts_with = "timing")]
+    pub modeline: Vec<DisplayTiming>,
+
+    #[clap(verbatim_doc_comment)]
+    /// Same as --modeline, but giving only the relevant timings:
+    /// pixel clock (in MHz), visible width, total width,
+    /// visible height and total height.
+    /// Example: --timing 72.40,1366,1526,768,790
+    /// With several displays, give it once for each of them, in order.
+    #[arg(long, value_name = "CLOCK_MHZ,HDISPLAY,HTOTAL,VDISPLAY,VTOTAL",
+          value_parser = DisplayTiming::from_short_timing)]
+    pub timing: Vec<DisplayTiming>,
+
+    #[clap(verbatim_doc_comment)]
+    /// Read the display timing from the monitor's EDID,
+    /// which has the exact pixel clock and blanking intervals.
+    /// If no PATH is given, the EDIDs of all connected monitors are
+    /// looked up in /sys/class/drm (Linux only), and the first timing
+    /// matching the screen's height is used.
+    /// Can only be used with a single display.
+    #[arg(long, value_name = "PATH", num_args = 0..=1,
+          conflicts_with_all = ["modeline", "timing"])]
+    pub edid: Option<Option<PathBuf>>,
 
     #[clap(verbatim_doc_comment)]
     /// The file to be played.
@@ -20,17 +65,231 @@ pub struct Args {
     /// with the format explained in README.md.
     pub filename: String,
 
-    /// Use cosine waves instead of square waves as signal.
-    #[arg(long)]
+    #[clap(verbatim_doc_comment)]
+    /// Shape of the waves used as signal: square (or pulse, which is
+    /// the same, see --duty), cosine, triangle, sawtooth, or the path
+    /// to a wavetable file with a single cycle (see README.md).
+    #[arg(long, value_name = "SHAPE", default_value = "square",
+          value_parser = parse_waveform)]
+    pub waveform: Waveform,
+
+    /// Same as --waveform cosine.
+    #[arg(long, conflicts_with = "waveform")]
     pub cosine: bool,
 
+    #[clap(verbatim_doc_comment)]
+    /// Percentage of each cycle of a square wave during which
+    /// rows are white. Lower values make the sound quieter,
+    /// but richer in harmonics. Only used by square waves.
+    /// In text files, notes may override it (see README.md).
+    #[arg(long, value_name = "PERCENT", default_value_t = 50.0,
+          value_parser = parse_duty)]
+    pub duty: f64,
+
+    #[clap(verbatim_doc_comment)]
+    /// Modulation depth: how far the signal swings, as a percentage
+    /// of the range from black to white. Below 100, the rows are never
+    /// fully black nor fully white, as in conventional AM.
+    #[arg(long, value_name = "PERCENT", default_value_t = 100.0,
+          value_parser = parse_percentage)]
+    pub depth: f64,
+
+    #[clap(verbatim_doc_comment)]
+    /// Level the signal swings around, as a percentage of the range
+    /// from black to white. Lower it to make the screen darker.
+    #[arg(long, value_name = "PERCENT", default_value_t = 50.0,
+          value_parser = parse_percentage)]
+    pub offset: f64,
+
+    #[clap(verbatim_doc_comment)]
+    /// CRT mode: draw a carrier of the given frequency (in MHz) along
+    /// each row, switched on and off by the notes, for monitors that
+    /// scan pixels one at a time. Tune the radio to this frequency.
+    /// Requires the pixel clock (see --modeline, --timing and --edid).
+    #[arg(long, value_name = "FREQ_MHZ", conflicts_with = "lit_width")]
+    pub crt_carrier: Option<f64>,
+
+    #[clap(verbatim_doc_comment)]
+    /// Most times per second the screen may change from one note
+    /// (or rest) to the next. Faster changes are held back, and only
+    /// the latest one is shown once enough time has passed.
+    /// A note's stripes still scroll with every frame.
+    #[arg(long, value_name = "HZ", value_parser = parse_rate)]
+    pub max_change_rate: Option<f64>,
+
+    #[clap(verbatim_doc_comment)]
+    /// Reduce the contrast (to a depth of at most 25%, rests and pauses
+    /// included) and how often the screen changes from one note to the
+    /// next (to at most 3 times per second), so it doesn't flash.
+    /// A note's stripes still scroll with every frame.
+    /// The signal is weaker, but still receivable close to the monitor.
+    /// Can't be used with --lit-width or --crt-carrier, which always
+    /// paint some pixels black.
+    #[arg(long, conflicts_with_all = ["lit_width", "crt_carrier"])]
+    pub safe_mode: bool,
+
+    #[clap(verbatim_doc_comment)]
+    /// Don't show the photosensitivity warning at startup,
+    /// which waits for Continue to be clicked before playing.
+    #[arg(long)]
+    pub skip_warning: bool,
+
+    #[clap(verbatim_doc_comment)]
+    /// Paint rows where a square wave goes up or down in gray,
+    /// according to how long the wave was high during that row.
+    /// This makes high notes more in tune, especially on monitors
+    /// with a low horizontal refresh rate.
+    #[arg(long)]
+    pub antialias: bool,
+
+    #[clap(verbatim_doc_comment)]
+    /// Paint every row either black or white (or the signal's darkest and
+    /// brightest levels, with --depth and --offset), using sigma-delta
+    /// modulation of the given order (1 or 2) instead of gray levels.
+    /// Useful with cosine waves, or with several notes at once,
+    /// on monitors that don't emit in proportion to gray levels.
+    #[arg(long, value_name = "ORDER", value_parser = clap::value_parser!(u8).range(1..=2))]
+    pub sigma_delta: Option<u8>,
+
+    #[clap(verbatim_doc_comment)]
+    /// Paint a fraction of each row's pixels in white (and the rest in
+    /// black), in proportion to the signal, instead of using gray levels.
+    /// The lit pixels are either at the start of each row (contiguous,
+    /// the default), or spread out evenly across it (spread).
+    #[arg(long, value_enum, value_name = "LAYOUT", num_args = 0..=1,
+          default_missing_value = "contiguous", conflicts_with = "sigma_delta")]
+    pub lit_width: Option<CoverageLayout>,
+
+    #[clap(verbatim_doc_comment)]
+    /// How to dither the gray levels of waves other than square ones.
+    #[arg(long, value_enum, default_value_t = Dither::Gaussian)]
+    pub dither: Dither,
+
+    #[clap(verbatim_doc_comment)]
+    /// Seed for the dithering noise. With the same seed and options,
+    /// the same frames are rendered every time.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    #[clap(verbatim_doc_comment)]
+    /// File with measurements of how much the monitor emits at each
+    /// gray level, used to make the emitted signal proportional to the
+    /// waveform. See README.md for the format.
+    #[arg(long, value_name = "FILE")]
+    pub calibration: Option<PathBuf>,
+
+    #[clap(verbatim_doc_comment)]
+    /// Which sub-pixels carry the signal:
+    /// gray (all of them), red, green or blue (only that one),
+    /// R,G,B (each with the given brightness, between 0 and 1, e.g. 1,0.5,0),
+    /// or split (each note on its own channel: the first one on red,
+    /// the second on green, the third on blue, and so on;
+    /// with --midi, notes held at the same time go from lowest to highest).
+    #[arg(long, value_name = "MODE", default_value = "gray",
+          value_parser = ColorMode::parse)]
+    pub color: ColorMode,
+
     /// Use experimental MIDI player.
     #[arg(long)]
     pub midi: bool,
 
     #[clap(verbatim_doc_comment)]
     /// Which MIDI channel to play.
+    /// With several displays, either give it once for each of them,
+    /// in order, to play a different channel on each one, or once
+    /// to play the same channel on all of them.
     /// Ignored if --midi option was not used.
-    #[arg(short, long, default_value_t = 0)]
-    pub channel: u8,
+    #[arg(short, long, default_values_t = [0])]
+    pub channel: Vec<u8>,
+
+    #[clap(verbatim_doc_comment)]
+    /// How much memory (in MiB) to use for keeping rendered frames,
+    /// so that notes played again don't have to be rendered again.
+    /// Use 0 to disable.
+    #[arg(long, value_name = "MIB", default_value_t = 64)]
+    pub frame_cache_mb: usize,
+
+    #[clap(verbatim_doc_comment)]
+    /// Which display to use, either by index or (part of) its name.
+    /// The available displays are listed at startup.
+    /// Defaults to the first one. Repeat it to broadcast on several
+    /// displays at once, e.g. -d 0 -d 1.
+    #[arg(short, long, conflicts_with = "headless")]
+    pub display: Vec<String>,
+
+    #[clap(verbatim_doc_comment)]
+    /// How the display is rotated, as in `xrandr --rotate`,
+    /// so that the signal follows the order in which the panel
+    /// actually scans its rows. Detected if possible, when omitted.
+    #[arg(long, value_enum, conflicts_with = "headless")]
+    pub orientation: Option<Orientation>,
+
+    #[clap(verbatim_doc_comment)]
+    /// Don't open a window: render every frame into memory instead,
+    /// as if the screen had ROWS pixel rows, and print how many frames
+    /// were rendered at the end. Useful for testing without a monitor.
+    #[arg(long, value_name = "ROWS", value_parser = parse_rows)]
+    pub headless: Option<usize>,
+
+    #[clap(verbatim_doc_comment)]
+    /// At exit, print how late note changes reached the screen,
+    /// how long frames took to render and present, and how many
+    /// frames missed their deadline, with a histogram of each,
+    /// and how many frames were found in the frame cache.
+    #[arg(long)]
+    pub stats: bool,
+}
+
+fn parse_duty(duty: &str) -> Result<f64, String> {
+    let duty: f64 = duty.parse()
+        .map_err(|e| format!("{}", e))?;
+    if duty > 0.0 && duty <= 100.0 {
+        Ok(duty)
+    } else {
+        Err("must be above 0 and at most 100".to_string())
+    }
+}
+
+fn parse_percentage(percentage: &str) -> Result<f64, String> {
+    let percentage: f64 = percentage.parse()
+        .map_err(|e| format!("{}", e))?;
+    if (0.0..=100.0).contains(&percentage) {
+        Ok(percentage)
+    } else {
+        Err("must be between 0 and 100".to_string())
+    }
+}
+
+fn parse_rate(rate: &str) -> Result<f64, String> {
+    let rate: f64 = rate.parse()
+        .map_err(|e| format!("{}", e))?;
+    if rate > 0.0 && rate.is_finite() {
+        Ok(rate)
+    } else {
+        Err("must be above 0".to_string())
+    }
+}
+
+fn parse_rows(rows: &str) -> Result<usize, String> {
+    let rows: usize = rows.parse()
+        .map_err(|e| format!("{}", e))?;
+    if rows > 0 {
+        Ok(rows)
+    } else {
+        Err("must be at least 1".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn safe_mode_rejects_full_contrast_encodings() {
+        let parse = |args: &[&str]| Args::try_parse_from([&["tempest-lcd"], args].concat());
+        assert!(parse(&["--safe-mode", "--sigma-delta", "1", "song"]).is_ok());
+        assert!(parse(&["--safe-mode", "--lit-width", "song"]).is_err());
+        assert!(parse(&["--safe-mode", "--crt-carrier", "1.2", "song"]).is_err());
+    }
 }
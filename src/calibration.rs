@@ -0,0 +1,120 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+// Compensating for monitors whose emission isn't proportional to gray level.
+//
+// A calibration file has one measurement per line: a gray level (0 to 255)
+// followed by how much the monitor emitted while showing it, in any unit.
+// Blank lines and lines starting with '#' are ignored. For example:
+//
+//     # gray emission
+//     0    0.10
+//     64   0.18
+//     128  0.45
+//     255  1.00
+
+use std::{fs, path::Path};
+
+/// Maps the emission we want from a row to the gray level that produces it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Calibration {
+    // (emission, gray level) pairs, sorted by both,
+    // with emissions scaled to the range [0, 1]
+    points: Vec<(f64, f64)>,
+}
+
+impl Calibration {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut measurements = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [gray, emission] = fields[..] else {
+                return Err(format!("line {}: expected a gray level and an emission",
+                                   index + 1));
+            };
+            let gray: f64 = gray.parse()
+                .map_err(|e| format!("line {}: bad gray level '{}': {}", index + 1, gray, e))?;
+            let emission: f64 = emission.parse()
+                .map_err(|e| format!("line {}: bad emission '{}': {}", index + 1, emission, e))?;
+            if !(0.0..=255.0).contains(&gray) {
+                return Err(format!("line {}: gray level must be between 0 and 255",
+                                   index + 1));
+            }
+            measurements.push((gray, emission));
+        }
+        measurements.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        if measurements.len() < 2 {
+            return Err("calibration needs at least two measurements".to_string());
+        }
+        if measurements.windows(2).any(|pair| pair[1].1 <= pair[0].1) {
+            return Err("emission must increase with the gray level".to_string());
+        }
+        let min_emission = measurements[0].1;
+        let max_emission = measurements[measurements.len() - 1].1;
+        let points = measurements.iter()
+            .map(|(gray, emission)| {
+                ((emission - min_emission) / (max_emission - min_emission), *gray)
+            })
+            .collect();
+        Ok(Calibration { points })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read calibration file {}: {}", path.display(), e))?;
+        Self::parse(&text)
+            .map_err(|e| format!("invalid calibration file {}: {}", path.display(), e))
+    }
+
+    /// The (fractional) gray level whose emission is `level` of the way
+    /// between the darkest and brightest measured ones.
+    /// Emissions between measurements are linearly interpolated.
+    pub fn gray_level(&self, level: f64) -> f64 {
+        let level = level.clamp(0.0, 1.0);
+        // index of the first point above `level`, but never the first point
+        let next = self.points.partition_point(|(emission, _)| *emission <= level)
+            .clamp(1, self.points.len() - 1);
+        let (emission0, gray0) = self.points[next - 1];
+        let (emission1, gray1) = self.points[next];
+        gray0 + (gray1 - gray0) * (level - emission0) / (emission1 - emission0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_gray_levels() {
+        let calibration = Calibration::parse("\
+            # gray emission
+            255 5.0
+            0   1.0
+
+            100 3.0
+        ").unwrap();
+        assert_eq!(calibration.gray_level(0.0), 0.0);
+        assert_eq!(calibration.gray_level(0.25), 50.0);
+        assert_eq!(calibration.gray_level(0.5), 100.0);
+        assert_eq!(calibration.gray_level(1.0), 255.0);
+        assert_eq!(calibration.gray_level(1.5), 255.0);
+    }
+
+    #[test]
+    fn rejects_bad_calibration() {
+        assert!(Calibration::parse("0 1.0").is_err());
+        assert!(Calibration::parse("0 1.0\n255").is_err());
+        assert!(Calibration::parse("0 1.0\n300 2.0").is_err());
+        // emission goes down
+        assert!(Calibration::parse("0 1.0\n128 3.0\n255 2.0").is_err());
+    }
+}
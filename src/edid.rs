@@ -0,0 +1,272 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+// Reading display timings from EDID blobs, which is what monitors use to tell
+// the computer which modes they support.
+//
+// Only the detailed timing descriptors are decoded: they're the only part
+// that has the exact pixel clock and blanking intervals.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::timing::DisplayTiming;
+
+const BLOCK_LEN: usize = 128;
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const DESCRIPTOR_LEN: usize = 18;
+// where the 4 descriptors are in the base block
+const BASE_DESCRIPTORS_START: usize = 54;
+const BASE_DESCRIPTORS_COUNT: usize = 4;
+const CEA_EXTENSION_TAG: u8 = 0x02;
+// SDL reports refresh rates in whole hertz, e.g. 59 or 60 for 59.94Hz
+const REFRESH_RATE_TOLERANCE: f64 = 1.0;
+
+// where DRM exposes each connector's EDID on Linux
+const SYSFS_DRM_DIR: &str = "/sys/class/drm";
+
+/// Decode every (non-interlaced) detailed timing descriptor in an EDID blob,
+/// including those in CEA-861 extension blocks.
+/// The first one is the monitor's preferred mode.
+pub fn parse_edid(edid: &[u8]) -> Result<Vec<DisplayTiming>, String> {
+    if edid.len() < BLOCK_LEN {
+        return Err(format!("EDID is too short: {} bytes", edid.len()));
+    }
+    if edid[..HEADER.len()] != HEADER {
+        return Err("EDID has an invalid header".to_string());
+    }
+    check_block_sum(&edid[..BLOCK_LEN], 0)?;
+
+    let mut timings: Vec<DisplayTiming> = (0..BASE_DESCRIPTORS_COUNT)
+        .map(|i| BASE_DESCRIPTORS_START + i * DESCRIPTOR_LEN)
+        .filter_map(|start| parse_detailed_timing(&edid[start..start + DESCRIPTOR_LEN]))
+        .collect();
+
+    // extension blocks may be missing if the EDID was truncated
+    // (e.g. when read through some KVMs); just use what we have
+    let extension_count = edid[126] as usize;
+    for block_index in 1..=extension_count {
+        let block_start = block_index * BLOCK_LEN;
+        let Some(block) = edid.get(block_start..block_start + BLOCK_LEN) else {
+            break;
+        };
+        if block[0] != CEA_EXTENSION_TAG {
+            continue;
+        }
+        check_block_sum(block, block_index)?;
+        // byte 2 tells where the descriptors start; 0 means there are none
+        let mut start = block[2] as usize;
+        if start < 4 {
+            continue;
+        }
+        // the last byte is the checksum
+        while start + DESCRIPTOR_LEN < BLOCK_LEN {
+            match parse_detailed_timing(&block[start..start + DESCRIPTOR_LEN]) {
+                Some(timing) => timings.push(timing),
+                // padding
+                None => break,
+            }
+            start += DESCRIPTOR_LEN;
+        }
+    }
+    Ok(timings)
+}
+
+/// Like `parse_edid`, but reads the EDID from a file
+/// (such as `/sys/class/drm/card0-HDMI-A-1/edid`).
+pub fn read_edid_file(path: &Path) -> Result<Vec<DisplayTiming>, String> {
+    let edid = fs::read(path)
+        .map_err(|e| format!("failed to read EDID file {}: {}", path.display(), e))?;
+    parse_edid(&edid)
+        .map_err(|e| format!("failed to parse EDID file {}: {}", path.display(), e))
+}
+
+/// Paths to the EDID of every connector that currently has a monitor attached.
+pub fn find_connected_edids() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(SYSFS_DRM_DIR) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join("edid"))
+        // disconnected connectors still have the file, but it's empty
+        .filter(|path| fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Pick, among the timings in the given EDID files, the first one whose
+/// visible height matches the screen's and, if the current `refresh_rate`
+/// (in Hz) is known, whose refresh rate matches it too. If only the height
+/// matches, warn and use that one; if nothing matches, use the preferred
+/// timing of the first file. Also returns which file the timing came from.
+pub fn choose_timing(paths: &[PathBuf], rows: usize, refresh_rate: Option<f64>)
+    -> Result<(PathBuf, DisplayTiming), String> {
+    let mut candidates = Vec::new();
+    for path in paths {
+        for timing in read_edid_file(path)? {
+            candidates.push((path.clone(), timing));
+        }
+    }
+    choose_candidate(&candidates, rows, refresh_rate)
+}
+
+fn choose_candidate(candidates: &[(PathBuf, DisplayTiming)],
+                    rows: usize,
+                    refresh_rate: Option<f64>)
+    -> Result<(PathBuf, DisplayTiming), String> {
+    let same_height: Vec<&(PathBuf, DisplayTiming)> = candidates.iter()
+        .filter(|(_, timing)| timing.vdisplay as usize == rows)
+        .collect();
+    let Some(first_same_height) = same_height.first() else {
+        let preferred = candidates.first()
+            .ok_or_else(|| "no usable timing found in EDID".to_string())?;
+        eprintln!("warning: no EDID timing has {} visible rows; using the preferred one",
+                  rows);
+        return Ok(preferred.clone());
+    };
+    let Some(refresh_rate) = refresh_rate else {
+        return Ok((*first_same_height).clone());
+    };
+    let same_rate = same_height.iter()
+        .find(|(_, timing)| (timing.refresh_rate() - refresh_rate).abs() <= REFRESH_RATE_TOLERANCE);
+    match same_rate {
+        Some(candidate) => Ok((*candidate).clone()),
+        None => {
+            eprintln!("warning: no EDID timing with {} visible rows runs at the current {}Hz; \
+                       using one at {:.2}Hz",
+                      rows, refresh_rate, first_same_height.1.refresh_rate());
+            Ok((*first_same_height).clone())
+        },
+    }
+}
+
+fn check_block_sum(block: &[u8], block_index: usize) -> Result<(), String> {
+    let sum = block.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    if sum != 0 {
+        Err(format!("EDID block {} has an invalid checksum", block_index))
+    } else {
+        Ok(())
+    }
+}
+
+// Returns None if the descriptor is not a detailed timing descriptor
+// (i.e. it's a display descriptor, such as the monitor's name),
+// or if the timing is interlaced, since we assume every row is scanned
+// on every frame.
+fn parse_detailed_timing(desc: &[u8]) -> Option<DisplayTiming> {
+    // in units of 10kHz
+    let pixel_clock = u16::from_le_bytes([desc[0], desc[1]]);
+    if pixel_clock == 0 {
+        return None;
+    }
+    let interlaced = desc[17] & 0x80 != 0;
+    if interlaced {
+        return None;
+    }
+    // each of these is 12 bits long: the lower 8 bits have their own byte,
+    // while the upper 4 bits of each pair share a byte
+    let hactive = (desc[2] as u32) | ((desc[4] as u32 & 0xf0) << 4);
+    let hblank = (desc[3] as u32) | ((desc[4] as u32 & 0x0f) << 8);
+    let vactive = (desc[5] as u32) | ((desc[7] as u32 & 0xf0) << 4);
+    let vblank = (desc[6] as u32) | ((desc[7] as u32 & 0x0f) << 8);
+    Some(DisplayTiming {
+        pixel_clock: (pixel_clock as f64) * 10_000.0,
+        hdisplay: hactive,
+        htotal: hactive + hblank,
+        vdisplay: vactive,
+        vtotal: vactive + vblank,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAPTOP_EDID: &[u8] = include_bytes!("../test_data/edid/laptop_1366x768.bin");
+    const MONITOR_EDID: &[u8] = include_bytes!("../test_data/edid/monitor_1920x1080.bin");
+
+    #[test]
+    fn parse_laptop_edid() {
+        let timings = parse_edid(LAPTOP_EDID).unwrap();
+        assert_eq!(timings, vec![DisplayTiming {
+            pixel_clock: 72_400_000.0,
+            hdisplay: 1366,
+            htotal: 1526,
+            vdisplay: 768,
+            vtotal: 790,
+        }]);
+    }
+
+    #[test]
+    fn parse_edid_with_extension() {
+        let timings = parse_edid(MONITOR_EDID).unwrap();
+        let sizes: Vec<_> = timings.iter()
+            .map(|t| (t.pixel_clock, t.hdisplay, t.htotal, t.vdisplay, t.vtotal))
+            .collect();
+        assert_eq!(sizes, vec![
+            (148_500_000.0, 1920, 2200, 1080, 1125),
+            (74_250_000.0, 1280, 1650, 720, 750),
+            (27_000_000.0, 720, 858, 480, 525),
+        ]);
+    }
+
+    #[test]
+    fn choose_timing_matching_screen() {
+        let paths = [
+            PathBuf::from("test_data/edid/laptop_1366x768.bin"),
+            PathBuf::from("test_data/edid/monitor_1920x1080.bin"),
+        ];
+        let (path, timing) = choose_timing(&paths, 720, None).unwrap();
+        assert_eq!(path, paths[1]);
+        assert_eq!((timing.hdisplay, timing.vdisplay), (1280, 720));
+
+        // falls back to the first file's preferred timing
+        let (path, timing) = choose_timing(&paths, 1024, None).unwrap();
+        assert_eq!(path, paths[0]);
+        assert_eq!((timing.hdisplay, timing.vdisplay), (1366, 768));
+    }
+
+    #[test]
+    fn choose_timing_matching_refresh_rate() {
+        let timing = |pixel_clock| DisplayTiming {
+            pixel_clock,
+            hdisplay: 1920,
+            htotal: 2200,
+            vdisplay: 1080,
+            vtotal: 1125,
+        };
+        let path = PathBuf::from("edid");
+        // 60Hz and 50Hz
+        let candidates = [(path.clone(), timing(148_500_000.0)),
+                          (path.clone(), timing(123_750_000.0))];
+        let chosen = |refresh_rate| {
+            choose_candidate(&candidates, 1080, refresh_rate).unwrap().1.pixel_clock
+        };
+        assert_eq!(chosen(Some(50.0)), 123_750_000.0);
+        assert_eq!(chosen(Some(60.0)), 148_500_000.0);
+        // no match: the first one with the right height
+        assert_eq!(chosen(Some(75.0)), 148_500_000.0);
+        assert_eq!(chosen(None), 148_500_000.0);
+    }
+
+    #[test]
+    fn reject_bad_edid() {
+        assert!(parse_edid(&LAPTOP_EDID[..100]).is_err());
+
+        let mut bad_header = LAPTOP_EDID.to_vec();
+        bad_header[0] = 0x01;
+        assert!(parse_edid(&bad_header).is_err());
+
+        let mut bad_checksum = LAPTOP_EDID.to_vec();
+        bad_checksum[127] ^= 0x01;
+        assert!(parse_edid(&bad_checksum).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+// Turning the waveform we want to broadcast, one level per row,
+// into how each row is actually painted.
+
+use rand::{Rng, rngs::StdRng};
+use rand_distr::StandardNormal;
+
+use crate::{
+    calibration::Calibration,
+    frame_sink::{CoverageLayout, Row},
+};
+
+/// How the desired level of each row becomes what's painted on the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    /// Paint each row with the gray level closest to the desired one.
+    /// This relies on the monitor emitting more for brighter grays.
+    #[default]
+    Gray,
+    /// Paint each row at either extreme of the signal (black or white,
+    /// at full depth), with sigma-delta modulation:
+    /// the error made on each row is carried over to the next ones, so that
+    /// it's mostly made of frequencies too high to be heard.
+    /// `order` is either 1 or 2; higher orders push more of the error out
+    /// of the audio band, but get unstable more easily.
+    SigmaDelta { order: u8 },
+    /// Light a fraction of each row's pixels in proportion to the desired
+    /// level, leaving the others black. This gives many more steps than
+    /// the 256 gray levels, and doesn't depend on the panel's gamma.
+    Width(CoverageLayout),
+}
+
+/// How to keep the error from rounding to the nearest gray level
+/// from being correlated with the signal, which would make it audible.
+/// Only applies to the `Gray` encoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Dither {
+    /// Just round down.
+    None,
+    /// Add gaussian noise, with a standard deviation of one gray level.
+    #[default]
+    Gaussian,
+    /// Add triangular noise (the sum of two uniform ones),
+    /// between minus one and one gray level.
+    Tpdf,
+    /// Carry each row's rounding error over to the next row.
+    ErrorDiffusion,
+}
+
+/// Paint `levels`, between 0 (black) and 1 (white), into the given channel
+/// (0 for red, 1 for green, 2 for blue) of `rows`.
+/// `extremes` are the lowest and highest levels the signal swings between,
+/// which are the only ones painted with sigma-delta modulation.
+/// Without a calibration, emission is assumed to be proportional
+/// to the gray level.
+#[allow(clippy::too_many_arguments)]
+pub fn encode(encoding: Encoding,
+              dither: Dither,
+              calibration: Option<&Calibration>,
+              extremes: (f64, f64),
+              levels: &[f64],
+              rows: &mut [Row],
+              channel: usize,
+              rng: &mut StdRng) {
+    match encoding {
+        Encoding::Gray => encode_gray(dither, calibration, levels, rows, channel, rng),
+        Encoding::SigmaDelta { order } => {
+            encode_sigma_delta(order, extremes, levels, rows, channel)
+        },
+        Encoding::Width(_) => encode_width(levels, rows, channel),
+    }
+}
+
+fn encode_gray(dither: Dither,
+               calibration: Option<&Calibration>,
+               levels: &[f64],
+               rows: &mut [Row],
+               channel: usize,
+               rng: &mut StdRng) {
+    // only used by error diffusion
+    let mut error = 0.0;
+    for (level, row) in levels.iter().zip(rows) {
+        row.coverage[channel] = Row::FULL;
+        let gray = match calibration {
+            Some(calibration) => calibration.gray_level(*level),
+            None => 255.0 * level,
+        };
+        row.color[channel] = match dither {
+            Dither::None => gray as u8,
+            Dither::Gaussian => {
+                let noise: f64 = rng.sample(StandardNormal);
+                (gray + noise) as u8
+            },
+            Dither::Tpdf => {
+                let noise = rng.gen::<f64>() - rng.gen::<f64>();
+                (gray + noise) as u8
+            },
+            Dither::ErrorDiffusion => {
+                let wanted = gray + error;
+                let painted = wanted.clamp(0.0, 255.0).floor();
+                error = wanted - painted;
+                painted as u8
+            },
+        };
+    }
+}
+
+fn encode_sigma_delta(order: u8,
+                      (low, high): (f64, f64),
+                      levels: &[f64],
+                      rows: &mut [Row],
+                      channel: usize) {
+    let (low, high) = (low.clamp(0.0, 1.0), high.clamp(0.0, 1.0));
+    let grays = [(255.0 * low).round() as u8, (255.0 * high).round() as u8];
+    // quantization errors (output minus input to the quantizer)
+    // of the previous two rows
+    let mut last_error = 0.0;
+    let mut second_last_error = 0.0;
+    for (level, row) in levels.iter().zip(rows) {
+        // where the level is between the extremes, from 0 to 1
+        let level = if high > low { (level - low) / (high - low) } else { 0.5 };
+        // Subtracting the past errors shapes the total error as
+        // (1 - z^-1)^order, i.e. a high-pass filter.
+        let wanted = match order {
+            1 => level - last_error,
+            _ => level - 2.0 * last_error + second_last_error,
+        };
+        let is_high = wanted >= 0.5;
+        let output = if is_high { 1.0 } else { 0.0 };
+        // keep the error bounded, so the modulator recovers after
+        // a stretch of levels near black or white
+        let error = (output - wanted).clamp(-1.0, 1.0);
+        second_last_error = last_error;
+        last_error = error;
+        row.color[channel] = grays[is_high as usize];
+        row.coverage[channel] = Row::FULL;
+    }
+}
+
+fn encode_width(levels: &[f64], rows: &mut [Row], channel: usize) {
+    for (level, row) in levels.iter().zip(rows) {
+        row.color[channel] = 255;
+        row.coverage[channel] = (level.clamp(0.0, 1.0) * Row::FULL as f64).round() as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    // the red channel of the encoded rows
+    fn encode_levels(encoding: Encoding,
+                     dither: Dither,
+                     calibration: Option<&Calibration>,
+                     levels: &[f64]) -> Vec<u8> {
+        let mut rows = vec![Row::solid([0; 3]); levels.len()];
+        let mut rng = StdRng::seed_from_u64(7);
+        encode(encoding, dither, calibration, (0.0, 1.0), levels, &mut rows, 0, &mut rng);
+        rows.iter().map(|row| row.color[0]).collect()
+    }
+
+    #[test]
+    fn gray_encoding() {
+        let rows = encode_levels(Encoding::Gray, Dither::None, None, &[0.0, 0.5, 1.0]);
+        assert_eq!(rows, vec![0, 127, 255]);
+    }
+
+    #[test]
+    fn calibrated_gray_encoding() {
+        // emission grows with the square of the gray level
+        let calibration = Calibration::parse("0 0\n51 1\n102 4\n153 9\n204 16\n255 25").unwrap();
+        let rows = encode_levels(Encoding::Gray, Dither::None, Some(&calibration),
+                                 &[0.0, 0.16, 1.0]);
+        assert_eq!(rows, vec![0, 102, 255]);
+    }
+
+    #[test]
+    fn error_diffusion_keeps_average_level() {
+        // 127.75 on every row
+        let rows = encode_levels(Encoding::Gray, Dither::ErrorDiffusion, None, &[0.501; 4]);
+        assert_eq!(rows, vec![127, 128, 128, 128]);
+    }
+
+    #[test]
+    fn dither_is_reproducible() {
+        let levels = [0.3; 16];
+        for dither in [Dither::Gaussian, Dither::Tpdf] {
+            let first = encode_levels(Encoding::Gray, dither, None, &levels);
+            let second = encode_levels(Encoding::Gray, dither, None, &levels);
+            assert_eq!(first, second);
+            // 76.5, give or take the noise
+            assert!(first.iter().all(|row| (70..=83).contains(row)), "{:?}", first);
+        }
+    }
+
+    #[test]
+    fn sigma_delta_keeps_average_level() {
+        for order in [1, 2] {
+            let encoding = Encoding::SigmaDelta { order };
+            let rows = encode_levels(encoding, Dither::None, None, &[0.25; 64]);
+            assert!(rows.iter().all(|row| *row == 0 || *row == 255));
+            let lit = rows.iter().filter(|row| **row == 255).count();
+            assert!((14..=18).contains(&lit), "order {}: {} rows lit", order, lit);
+        }
+    }
+
+    #[test]
+    fn sigma_delta_between_extremes() {
+        let mut rows = vec![Row::solid([0; 3]); 64];
+        let mut rng = StdRng::seed_from_u64(7);
+        // a quarter of the way from 0.25 to 0.75
+        encode(Encoding::SigmaDelta { order: 1 }, Dither::None, None, (0.25, 0.75),
+               &[0.375; 64], &mut rows, 0, &mut rng);
+        assert!(rows.iter().all(|row| row.color[0] == 64 || row.color[0] == 191));
+        let high = rows.iter().filter(|row| row.color[0] == 191).count();
+        assert!((14..=18).contains(&high), "{} rows high", high);
+    }
+
+    #[test]
+    fn width_encoding() {
+        let mut rows = vec![Row::solid([0; 3]); 3];
+        let mut rng = StdRng::seed_from_u64(0);
+        encode(Encoding::Width(CoverageLayout::Contiguous), Dither::None, None, (0.0, 1.0),
+               &[0.0, 0.25, 1.0], &mut rows, 1, &mut rng);
+        let coverages: Vec<u16> = rows.iter().map(|row| row.coverage[1]).collect();
+        assert_eq!(coverages, vec![0, 16384, Row::FULL]);
+        assert!(rows.iter().all(|row| row.color == [0, 255, 0]));
+        // other channels are left alone
+        assert!(rows.iter().all(|row| row.coverage[0] == Row::FULL));
+    }
+}
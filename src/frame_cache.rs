@@ -0,0 +1,122 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    mem,
+};
+
+struct CachedFrame<T> {
+    rows: Vec<T>,
+    // value of `FrameCache::uses` when this frame was last used
+    last_use: u64,
+}
+
+/// Keeps previously rendered frames around, so they don't have to be
+/// rendered again when the same notes are played.
+/// Once the frames take more than the given number of bytes,
+/// the least recently used ones are thrown away.
+pub struct FrameCache<K: Hash + Eq + Clone, T: Copy> {
+    max_bytes: usize,
+    used_bytes: usize,
+    frames: HashMap<K, CachedFrame<T>>,
+    uses: u64,
+}
+
+impl<K: Hash + Eq + Clone, T: Copy> FrameCache<K, T> {
+    /// A cache of `max_bytes` bytes; if zero, the cache is disabled.
+    pub fn new(max_bytes: usize) -> Self {
+        FrameCache {
+            max_bytes,
+            used_bytes: 0,
+            frames: HashMap::new(),
+            uses: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&[T]> {
+        self.uses += 1;
+        let uses = self.uses;
+        self.frames.get_mut(key).map(|frame| {
+            frame.last_use = uses;
+            frame.rows.as_slice()
+        })
+    }
+
+    pub fn insert(&mut self, key: K, rows: &[T]) {
+        let bytes = mem::size_of_val(rows);
+        if bytes > self.max_bytes {
+            return;
+        }
+        while self.used_bytes + bytes > self.max_bytes {
+            self.evict_least_recently_used();
+        }
+        self.uses += 1;
+        let frame = CachedFrame {
+            rows: rows.to_vec(),
+            last_use: self.uses,
+        };
+        self.used_bytes += bytes;
+        if let Some(old_frame) = self.frames.insert(key, frame) {
+            self.used_bytes -= mem::size_of_val(old_frame.rows.as_slice());
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        // A linear search is fine here: it only happens when the cache is
+        // full, and even then, it's much cheaper than rendering a frame.
+        let oldest_key = self.frames.iter()
+            .min_by_key(|(_, frame)| frame.last_use)
+            .map(|(key, _)| key.clone());
+        if let Some(frame) = oldest_key.and_then(|key| self.frames.remove(&key)) {
+            self.used_bytes -= mem::size_of_val(frame.rows.as_slice());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_cache_keeps_nothing() {
+        let mut cache = FrameCache::<_, u8>::new(0);
+        assert!(!cache.is_enabled());
+        cache.insert(1, &[0, 255]);
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = FrameCache::<_, u8>::new(6);
+        cache.insert(1, &[1, 1]);
+        cache.insert(2, &[2, 2]);
+        cache.insert(3, &[3, 3]);
+        // 1 is now more recently used than 2
+        assert_eq!(cache.get(&1), Some([1, 1].as_slice()));
+        cache.insert(4, &[4, 4]);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some([1, 1].as_slice()));
+        assert_eq!(cache.get(&3), Some([3, 3].as_slice()));
+        assert_eq!(cache.get(&4), Some([4, 4].as_slice()));
+    }
+
+    #[test]
+    fn replacing_frame_frees_its_memory() {
+        let mut cache = FrameCache::<_, u8>::new(4);
+        cache.insert(1, &[1, 1]);
+        cache.insert(1, &[5, 5]);
+        cache.insert(2, &[2, 2]);
+        assert_eq!(cache.get(&1), Some([5, 5].as_slice()));
+        assert_eq!(cache.get(&2), Some([2, 2].as_slice()));
+    }
+}
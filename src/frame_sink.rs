@@ -0,0 +1,782 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+use std::{cell::RefCell, mem::ManuallyDrop, rc::Rc};
+use sdl2::{
+    EventPump,
+    Sdl,
+    VideoSubsystem,
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    pixels::{Color, PixelFormatEnum},
+    render::{Texture, WindowCanvas},
+    video,
+};
+
+use crate::timing::ScanTiming;
+
+/// Where the frames rendered by `Gui` end up.
+///
+/// A frame is always given as one `Row` per pixel row, from top to bottom,
+/// since the panel updates a whole row at once anyway.
+pub trait FrameSink {
+    /// Number of pixel rows in each frame.
+    fn rows(&self) -> usize;
+
+    /// Display a frame made of the given rows, with their pixels laid out
+    /// according to `pattern`.
+    /// `rows` must have exactly `self.rows()` elements.
+    fn present_rows(&mut self, rows: &[Row], pattern: RowPattern);
+
+    /// Display a frame filled with a single color.
+    fn clear_and_present(&mut self, clear_color: Color);
+
+    /// Returns whether what was last presented got lost (e.g. the window
+    /// was covered and exposed again), and must be presented again.
+    /// Sinks sharing their events (like SDL windows) may report it for
+    /// each other, so this means any of them.
+    /// Pausing is left to `Gui`, which blanks the screen.
+    fn handle_events(&mut self, running: &mut bool, paused: &mut bool) -> bool;
+
+    /// Figure out the scan timing of whatever is displaying the frames,
+    /// if possible.
+    fn detect_scan_timing(&self) -> Option<ScanTiming> {
+        None
+    }
+
+    /// Refresh rate (in Hz) of the current display mode, if known.
+    fn refresh_rate(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// How a single pixel row is painted: for each channel (red, green, blue)
+/// `c`, a `coverage[c] / Row::FULL` fraction of the row's pixels have
+/// that channel set to `color[c]`, while the others have it set to 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Row {
+    pub color: [u8; 3],
+    pub coverage: [u16; 3],
+}
+
+impl Row {
+    pub const FULL: u16 = u16::MAX;
+
+    /// A row whose pixels are all the same color.
+    pub fn solid(color: [u8; 3]) -> Self {
+        Row {
+            color,
+            coverage: [Row::FULL; 3],
+        }
+    }
+
+    fn is_solid(&self) -> bool {
+        self.coverage == [Row::FULL; 3]
+    }
+
+    /// The color of the row, averaged over all of its pixels.
+    #[cfg(test)]
+    pub fn average_color(&self) -> [u8; 3] {
+        let mut average = [0; 3];
+        for (channel, average) in average.iter_mut().enumerate() {
+            let sum = self.color[channel] as u32 * self.coverage[channel] as u32;
+            *average = (sum / Row::FULL as u32) as u8;
+        }
+        average
+    }
+}
+
+/// Which pixels of a row are lit, when only some of them are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum CoverageLayout {
+    /// The ones at the start of the row.
+    #[default]
+    Contiguous,
+    /// Ones spread out evenly across the row, like an ordered dither.
+    Spread,
+}
+
+impl CoverageLayout {
+    // For each of the `columns` pixels of a row: the pixel is lit if
+    // the coverage is above this threshold.
+    // With `Contiguous`, they go up along the row, so the lit pixels
+    // are always a prefix of it.
+    fn thresholds(self, columns: usize) -> Vec<u16> {
+        // the order in which pixels get lit as coverage goes up
+        let mut order: Vec<usize> = (0..columns).collect();
+        if self == CoverageLayout::Spread {
+            // Reversing the bits of each index gives the order of
+            // a 1D ordered dither (e.g. 0, 4, 2, 6, 1, 5, 3, 7 for 8 pixels),
+            // in which every prefix is spread out as evenly as possible.
+            let bits = columns.next_power_of_two().trailing_zeros();
+            order.sort_by_key(|column| reverse_bits(*column, bits));
+        }
+        let mut thresholds = vec![0; columns];
+        for (rank, column) in order.into_iter().enumerate() {
+            // coverage is a whole number, so being above the exact threshold
+            // is the same as being above its integer part
+            let threshold = (rank as f64 + 0.5) / (columns as f64) * (Row::FULL as f64);
+            thresholds[column] = threshold as u16;
+        }
+        thresholds
+    }
+}
+
+/// A carrier wave drawn along each row, for monitors that (like CRTs) scan
+/// pixels one at a time, rather than a whole row at once.
+/// Pixels are lit during the first half of each carrier cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Carrier {
+    /// Carrier frequency divided by the pixel clock.
+    pub cycles_per_pixel: f64,
+    /// Carrier cycles during each row, including the horizontal blanking.
+    pub cycles_per_row: f64,
+    /// Phase (in cycles) of the carrier when the frame's first pixel
+    /// is scanned.
+    pub start_phase: f64,
+}
+
+impl Carrier {
+    fn is_high(&self, row: usize, column: usize) -> bool {
+        let phase = self.start_phase
+            + (row as f64) * self.cycles_per_row
+            + (column as f64) * self.cycles_per_pixel;
+        phase.fract() < 0.5
+    }
+}
+
+/// How the pixels within each row are painted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RowPattern {
+    /// According to each row's coverage.
+    Coverage(CoverageLayout),
+    /// With the row's color where the carrier is high, and black elsewhere.
+    /// Coverage is ignored.
+    Carrier(Carrier),
+}
+
+impl Default for RowPattern {
+    fn default() -> Self {
+        RowPattern::Coverage(CoverageLayout::default())
+    }
+}
+
+// the lowest `bits` bits of `value`, in reverse order
+fn reverse_bits(value: usize, bits: u32) -> usize {
+    (0..bits).fold(0, |reversed, bit| (reversed << 1) | ((value >> bit) & 1))
+}
+
+/// How the desktop is rotated relative to the panel,
+/// using the same names as `xrandr --rotate`.
+///
+/// Panels always scan along their own (native) rows, so this tells us
+/// which way those go in SDL's coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Orientation {
+    /// Panel rows are SDL rows, scanned top to bottom,
+    /// each one from left to right.
+    Normal,
+    /// Rotated 90 degrees counterclockwise:
+    /// panel rows are SDL columns, scanned right to left,
+    /// each one from top to bottom.
+    Left,
+    /// Rotated 90 degrees clockwise:
+    /// panel rows are SDL columns, scanned left to right,
+    /// each one from bottom to top.
+    Right,
+    /// Upside down: panel rows are SDL rows, scanned bottom to top,
+    /// each one from right to left.
+    Inverted,
+}
+
+impl Orientation {
+    // SDL only tells whether the display is in portrait or landscape mode,
+    // so this assumes the panel itself is natively landscape.
+    fn from_sdl(orientation: video::Orientation) -> Option<Self> {
+        match orientation {
+            video::Orientation::Landscape => Some(Orientation::Normal),
+            video::Orientation::LandscapeFlipped => Some(Orientation::Inverted),
+            video::Orientation::Portrait => Some(Orientation::Left),
+            video::Orientation::PortraitFlipped => Some(Orientation::Right),
+            video::Orientation::Unknown => None,
+        }
+    }
+
+    fn is_rotated(self) -> bool {
+        matches!(self, Orientation::Left | Orientation::Right)
+    }
+}
+
+/// Draws frames to a fullscreen SDL window.
+pub struct SdlSink {
+    // note: this must be held here to ensure it's not dropped
+    // until after the GUI stopped.
+    sdl_context: Sdl,
+    video_subsys: VideoSubsystem,
+    // Textures aren't destroyed when dropped (see Cargo.toml), so `drop`
+    // destroys them, before `canvas`, which created them, is dropped.
+    // A single row (or column, if rotated) of pixels, one per panel row,
+    // which is stretched over the entire window.
+    texture: ManuallyDrop<Texture>,
+    // A texture as large as the window, only created (and used)
+    // when some rows aren't solid.
+    full_texture: Option<Texture>,
+    canvas: WindowCanvas,
+    // `CoverageLayout::thresholds` for `thresholds_layout`,
+    // which is the last layout used
+    thresholds: Vec<u16>,
+    thresholds_layout: Option<CoverageLayout>,
+    // what `full_texture` currently holds, to skip filling it again
+    // with the same frame (e.g. one that came from the cache)
+    full_texture_rows: Vec<Row>,
+    full_texture_pattern: Option<RowPattern>,
+    // SDL only has one, shared by every window
+    event_pump: Rc<RefCell<EventPump>>,
+    // of every window, including this one
+    window_ids: Rc<[u32]>,
+    display_index: i32,
+    orientation: Orientation,
+    res_x: i32,
+    res_y: i32,
+}
+
+impl SdlSink {
+    /// Open one window on each of the given displays, which are either
+    /// indexes or (part of) names. `None` stands for the first display.
+    /// If `orientation` is `None`, it's detected (if possible) for each one.
+    pub fn create_all(displays: &[Option<&str>], orientation: Option<Orientation>) -> Vec<Self> {
+        let sdl_context = sdl2::init()
+            .unwrap_or_else(|e| panic!("failed to initialize SDL2: {}", e));
+        let video_subsys = sdl_context.video()
+            .unwrap_or_else(|e| panic!("failed to initialize video subsystem: {}", e));
+        let event_pump = sdl_context.event_pump()
+            .unwrap_or_else(|e| panic!("failed to get event pump: {}", e));
+        let event_pump = Rc::new(RefCell::new(event_pump));
+        // otherwise, focusing one of our windows would minimize the others
+        sdl2::hint::set("SDL_VIDEO_MINIMIZE_ON_FOCUS_LOSS", "0");
+
+        let mut sinks: Vec<Self> = choose_displays(&video_subsys, displays).into_iter()
+            .enumerate()
+            .map(|(index, display_index)| Self::create(&sdl_context,
+                                                       &video_subsys,
+                                                       &event_pump,
+                                                       display_index,
+                                                       orientation,
+                                                       index == 0))
+            .collect();
+        let window_ids: Rc<[u32]> = sinks.iter()
+            .map(|sink| sink.canvas.window().id())
+            .collect();
+        for sink in sinks.iter_mut() {
+            sink.window_ids = window_ids.clone();
+        }
+        sinks
+    }
+
+    fn create(sdl_context: &Sdl,
+              video_subsys: &VideoSubsystem,
+              event_pump: &Rc<RefCell<EventPump>>,
+              display_index: i32,
+              orientation: Option<Orientation>,
+              vsync: bool) -> Self {
+        let bounds = video_subsys.display_bounds(display_index)
+            .unwrap_or_else(|e| panic!("failed to get bounds of display {}: {}", display_index, e));
+
+        // fullscreen windows go to whichever display they're placed on
+        let mut window_builder = video_subsys.window("tempest LCD",
+                                                     bounds.width(),
+                                                     bounds.height());
+        window_builder.position(bounds.x(), bounds.y());
+        window_builder.fullscreen_desktop();
+        window_builder.borderless();
+        // otherwise, on scaled desktops, we'd be drawing to a smaller
+        // window which is then upscaled, and wouldn't control each row
+        window_builder.allow_highdpi();
+
+        let window = window_builder.build()
+            .unwrap_or_else(|e| panic!("failed to create window: {}", e));
+        let (logical_x, logical_y) = window.size();
+
+        // presenting in sync with the monitor keeps each frame from being
+        // torn between two renders, and paces the player to the refresh rate.
+        // Note that `Gui` doesn't learn when vblank happens: it assumes frames
+        // start on a nominal grid from its own clock, which drifts against
+        // the monitor's, so notes are only kept in phase approximately.
+        // Only one window waits for it, since each wait blocks until its own
+        // monitor's vblank, which would make the others miss theirs.
+        let mut canvas_builder = window.into_canvas();
+        if vsync {
+            canvas_builder = canvas_builder.present_vsync();
+        }
+        let canvas = canvas_builder.build()
+            .unwrap_or_else(|e| panic!("failed to make renderer from window: {}", e));
+
+        // The window size is in logical points, which may not be the same as
+        // physical pixels on scaled (HiDPI) desktops. What we care about
+        // is the physical rows, since that's what the monitor scans.
+        let (res_x_uint, res_y_uint) = canvas.output_size()
+            .unwrap_or_else(|e| panic!("failed to get renderer output size: {}", e));
+        if (res_x_uint, res_y_uint) != (logical_x, logical_y) {
+            eprintln!("warning: window is {}x{} points, but {}x{} pixels; using pixels",
+                      logical_x, logical_y, res_x_uint, res_y_uint);
+        }
+
+        let res_x: i32 = res_x_uint.try_into()
+            .unwrap_or_else(|e| panic!("failed to convert X resolution {} to i32: {}", res_x_uint, e));
+        let res_y: i32 = res_y_uint.try_into()
+            .unwrap_or_else(|e| panic!("failed to convert Y resolution {} to i32: {}", res_y_uint, e));
+
+        let orientation = orientation.unwrap_or_else(|| {
+            let sdl_orientation = video_subsys.display_orientation(display_index);
+            let detected = Orientation::from_sdl(sdl_orientation);
+            match detected {
+                Some(orientation) => println!("detected display orientation: {:?}", orientation),
+                None => println!("unknown display orientation: assuming normal"),
+            }
+            detected.unwrap_or(Orientation::Normal)
+        });
+
+        // the texture must not be smoothed when stretched,
+        // or neighboring rows would bleed into each other
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let (texture_width, texture_height) = if orientation.is_rotated() {
+            (res_x_uint, 1)
+        } else {
+            (1, res_y_uint)
+        };
+        let texture = canvas.texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, texture_width, texture_height)
+            .unwrap_or_else(|e| panic!("failed to create texture: {}", e));
+
+        SdlSink {
+            sdl_context: sdl_context.clone(),
+            video_subsys: video_subsys.clone(),
+            texture: ManuallyDrop::new(texture),
+            full_texture: None,
+            canvas,
+            thresholds: Vec::new(),
+            thresholds_layout: None,
+            full_texture_rows: Vec::new(),
+            full_texture_pattern: None,
+            event_pump: event_pump.clone(),
+            window_ids: Rc::from([]),
+            display_index,
+            orientation,
+            res_x,
+            res_y,
+        }
+    }
+}
+
+impl Drop for SdlSink {
+    fn drop(&mut self) {
+        // safety: the textures aren't used after this,
+        // and the renderer that created them still exists
+        unsafe {
+            ManuallyDrop::take(&mut self.texture).destroy();
+            if let Some(full_texture) = self.full_texture.take() {
+                full_texture.destroy();
+            }
+        }
+    }
+}
+
+impl SdlSink {
+    /// Keep the window black until a key is pressed.
+    /// Returns `false` if the user quit instead (with Q or by closing it).
+    pub fn wait_for_keypress(&mut self) -> bool {
+        clear_and_present(&mut self.canvas, Color::BLACK);
+        loop {
+            let event = self.event_pump.borrow_mut().wait_event();
+            match event {
+                Event::Quit {..}
+                    | Event::Window { win_event: WindowEvent::Close, .. }
+                    | Event::KeyDown { keycode: Some(Keycode::Q), .. } => return false,
+                Event::KeyDown {..} => return true,
+                Event::Window {..} => clear_and_present(&mut self.canvas, Color::BLACK),
+                _ => {},
+            }
+        }
+    }
+
+    fn fill_texture(&mut self, rows: &[Row]) {
+        let rotated = self.orientation.is_rotated();
+        self.texture.with_lock(None, |pixels, pitch| {
+            for (index, row) in rows.iter().enumerate() {
+                // a column texture has one pixel per line,
+                // while a row texture has all of them in one line
+                let offset = if rotated { 3 * index } else { pitch * index };
+                pixels[offset..offset + 3].copy_from_slice(&row.color);
+            }
+        }).unwrap_or_else(|e| panic!("failed to update texture: {}", e));
+    }
+
+    // Like `fill_texture`, but painting every pixel,
+    // for rows that aren't solid or have a carrier.
+    fn fill_full_texture(&mut self, rows: &[Row], pattern: RowPattern) {
+        if self.full_texture_pattern == Some(pattern) && self.full_texture_rows == rows {
+            return;
+        }
+        let rotated = self.orientation.is_rotated();
+        // pixels in each panel row
+        let columns = if rotated { self.res_y } else { self.res_x } as usize;
+        // with a carrier, the thresholds are unused, but any will do
+        let layout = match pattern {
+            RowPattern::Coverage(layout) => layout,
+            RowPattern::Carrier(_) => self.thresholds_layout.unwrap_or_default(),
+        };
+        if self.thresholds_layout != Some(layout) {
+            self.thresholds = layout.thresholds(columns);
+            self.thresholds_layout = Some(layout);
+        }
+        let thresholds = &self.thresholds;
+
+        let (width, height) = (self.res_x as u32, self.res_y as u32);
+        let texture = self.full_texture.get_or_insert_with(|| {
+            self.canvas.texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+                .unwrap_or_else(|e| panic!("failed to create texture: {}", e))
+        });
+        texture.with_lock(None, |pixels, pitch| {
+            // where the `x`th pixel of the `y`th panel row starts
+            let offset = |y: usize, x: usize| {
+                if rotated { pitch * x + 3 * y } else { pitch * y + 3 * x }
+            };
+            for (y, row) in rows.iter().enumerate() {
+                match pattern {
+                    RowPattern::Coverage(CoverageLayout::Contiguous) => {
+                        // each channel is lit on a prefix of the row, so the row
+                        // is made of at most 4 spans of a single color
+                        let lit_columns = row.coverage
+                            .map(|coverage| thresholds.partition_point(|t| coverage > *t));
+                        let mut ends = lit_columns;
+                        ends.sort_unstable();
+                        let mut start = 0;
+                        for end in ends.into_iter().chain([columns]) {
+                            let mut color = [0; 3];
+                            for channel in 0..3 {
+                                if start < lit_columns[channel] {
+                                    color[channel] = row.color[channel];
+                                }
+                            }
+                            if rotated {
+                                for x in start..end {
+                                    let offset = offset(y, x);
+                                    pixels[offset..offset + 3].copy_from_slice(&color);
+                                }
+                            } else if start < end {
+                                let span = &mut pixels[offset(y, start)..offset(y, end)];
+                                for pixel in span.chunks_exact_mut(3) {
+                                    pixel.copy_from_slice(&color);
+                                }
+                            }
+                            start = end;
+                        }
+                    },
+                    RowPattern::Coverage(CoverageLayout::Spread) => {
+                        for (x, threshold) in thresholds.iter().enumerate() {
+                            let offset = offset(y, x);
+                            for channel in 0..3 {
+                                let is_lit = row.coverage[channel] > *threshold;
+                                pixels[offset + channel] =
+                                    if is_lit { row.color[channel] } else { 0 };
+                            }
+                        }
+                    },
+                    RowPattern::Carrier(carrier) => {
+                        for x in 0..columns {
+                            let offset = offset(y, x);
+                            let color = if carrier.is_high(y, x) { row.color } else { [0; 3] };
+                            pixels[offset..offset + 3].copy_from_slice(&color);
+                        }
+                    },
+                }
+            }
+        }).unwrap_or_else(|e| panic!("failed to update texture: {}", e));
+        self.full_texture_rows.clear();
+        self.full_texture_rows.extend_from_slice(rows);
+        self.full_texture_pattern = Some(pattern);
+    }
+}
+
+// Print every display SDL knows about, and return the index of the one
+// matching `display`.
+fn choose_displays(video_subsys: &VideoSubsystem, displays: &[Option<&str>]) -> Vec<i32> {
+    let num_displays = video_subsys.num_video_displays()
+        .unwrap_or_else(|e| panic!("failed to get number of displays: {}", e));
+    let names: Vec<String> = (0..num_displays)
+        .map(|i| video_subsys.display_name(i)
+             .unwrap_or_else(|e| panic!("failed to get name of display {}: {}", i, e)))
+        .collect();
+
+    let chosen: Vec<i32> = displays.iter().map(|display| match display {
+        None => 0,
+        Some(display) => match display.parse::<i32>() {
+            Ok(index) if (0..num_displays).contains(&index) => index,
+            Ok(index) => panic!("no display with index {}: there are only {}",
+                                index, num_displays),
+            Err(_) => {
+                let display_lower = display.to_lowercase();
+                names.iter()
+                    .position(|name| name.to_lowercase().contains(&display_lower))
+                    .unwrap_or_else(|| panic!("no display named '{}'", display)) as i32
+            },
+        },
+    }).collect();
+
+    println!("available displays:");
+    for (i, name) in names.iter().enumerate() {
+        let mode = video_subsys.current_display_mode(i as i32)
+            .map(|m| format!("{}x{} at {}Hz", m.w, m.h, m.refresh_rate))
+            .unwrap_or_else(|_| "unknown mode".to_string());
+        let marker = if chosen.contains(&(i as i32)) { '*' } else { ' ' };
+        println!("{} {}: {} ({})", marker, i, name, mode);
+    }
+    chosen
+}
+
+impl FrameSink for SdlSink {
+    // these are the panel's rows, which aren't SDL's rows if rotated
+    fn rows(&self) -> usize {
+        if self.orientation.is_rotated() {
+            self.res_x as usize
+        } else {
+            self.res_y as usize
+        }
+    }
+
+    fn present_rows(&mut self, rows: &[Row], pattern: RowPattern) {
+        let solid = matches!(pattern, RowPattern::Coverage(_))
+            && rows.iter().all(Row::is_solid);
+        if solid {
+            self.fill_texture(rows);
+        } else {
+            self.fill_full_texture(rows, pattern);
+        }
+        let texture = match &self.full_texture {
+            Some(full_texture) if !solid => full_texture,
+            _ => &self.texture,
+        };
+
+        // the texture holds panel rows in the order they're scanned,
+        // as are the pixels within each of them
+        let (flip_horizontal, flip_vertical) = match self.orientation {
+            Orientation::Normal => (false, false),
+            Orientation::Left => (true, false),
+            Orientation::Right => (false, true),
+            Orientation::Inverted => (true, true),
+        };
+        self.canvas.copy_ex(texture, None, None, 0.0, None,
+                            flip_horizontal, flip_vertical)
+            .unwrap_or_else(|e| panic!("failed to draw texture: {}", e));
+        self.canvas.present();
+    }
+
+    fn clear_and_present(&mut self, clear_color: Color) {
+        clear_and_present(&mut self.canvas, clear_color);
+    }
+
+    fn refresh_rate(&self) -> Option<f64> {
+        let mode = self.video_subsys.current_display_mode(self.display_index)
+            .unwrap_or_else(|e| panic!("failed to get current display mode: {}", e));
+        // SDL reports 0 if it doesn't know the refresh rate
+        (mode.refresh_rate > 0).then_some(mode.refresh_rate as f64)
+    }
+
+    fn detect_scan_timing(&self) -> Option<ScanTiming> {
+        let refresh_rate = self.refresh_rate()?;
+        // the mode's size may be in logical points,
+        // so use the number of physical rows instead
+        let line_rate = (self.rows() as f64) * refresh_rate;
+        println!("detected display mode {}x{} at {}Hz: using {} rows per second",
+                 self.res_x, self.res_y, refresh_rate, line_rate);
+        Some(ScanTiming::from_line_rate(line_rate))
+    }
+
+    fn handle_events(&mut self, running: &mut bool, paused: &mut bool) -> bool {
+        let events: Vec<Event> = self.event_pump.borrow_mut().poll_iter().collect();
+        let mut exposed = false;
+        let mut focus_lost = false;
+        for ev in events {
+            match ev {
+                Event::Quit {..} => *running = false,
+                // whichever window polls first gets the events of all of them
+                Event::Window { window_id: id, win_event, .. }
+                    if self.window_ids.contains(&id) => match win_event {
+                    WindowEvent::Close => *running = false,
+                    WindowEvent::FocusLost => focus_lost = true,
+                    // `Gui` repaints every window, since this may be another one's
+                    WindowEvent::Shown
+                        | WindowEvent::Exposed
+                        | WindowEvent::FocusGained => exposed = true,
+                    _ => {},
+                },
+                Event::KeyDown { keycode: Some(key), .. } => match key {
+                    Keycode::Q => *running = false,
+                    // when resuming, the player re-renders its notes,
+                    // since we don't have access to them
+                    Keycode::P | Keycode::Space => *paused = !*paused,
+                    _ => {},
+                },
+                _ => {},
+            }
+        }
+        // focus moving from one of our windows to another isn't a reason to pause
+        if focus_lost {
+            let focused = self.sdl_context.keyboard().focused_window_id();
+            if !focused.is_some_and(|id| self.window_ids.contains(&id)) {
+                *paused = true;
+            }
+        }
+        exposed
+    }
+}
+
+fn clear_and_present(canvas: &mut WindowCanvas, clear_color: Color) {
+    canvas.set_draw_color(clear_color);
+    canvas.clear();
+    canvas.present();
+}
+
+/// Keeps every frame in memory (or just counts them) instead of displaying it,
+/// so playback can happen without a screen (and be inspected afterwards).
+pub struct MemorySink {
+    rows: usize,
+    frames: Vec<Vec<Row>>,
+    // whether to keep `frames`, or only count them
+    keep_frames: bool,
+    frame_count: usize,
+}
+
+impl MemorySink {
+    pub fn new(rows: usize) -> Self {
+        MemorySink {
+            rows,
+            frames: Vec::new(),
+            keep_frames: true,
+            frame_count: 0,
+        }
+    }
+
+    /// Like `new`, but only counting frames, which keeps memory use
+    /// from growing during a whole song.
+    pub fn counting(rows: usize) -> Self {
+        MemorySink {
+            keep_frames: false,
+            ..MemorySink::new(rows)
+        }
+    }
+
+    /// Number of frames presented so far.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// All frames presented so far, oldest first,
+    /// with each row's color averaged over its pixels.
+    #[cfg(test)]
+    pub fn color_frames(&self) -> Vec<Vec<[u8; 3]>> {
+        self.frames.iter()
+            .map(|frame| frame.iter().map(Row::average_color).collect())
+            .collect()
+    }
+
+    /// Like `color_frames`, but with each color's components averaged
+    /// into a gray level.
+    #[cfg(test)]
+    pub fn frames(&self) -> Vec<Vec<u8>> {
+        self.color_frames().iter()
+            .map(|frame| {
+                frame.iter()
+                    .map(|[r, g, b]| ((*r as u16 + *g as u16 + *b as u16) / 3) as u8)
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl FrameSink for MemorySink {
+    fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn present_rows(&mut self, rows: &[Row], _pattern: RowPattern) {
+        assert_eq!(rows.len(), self.rows);
+        self.frame_count += 1;
+        if self.keep_frames {
+            self.frames.push(rows.to_vec());
+        }
+    }
+
+    fn clear_and_present(&mut self, clear_color: Color) {
+        let color = [clear_color.r, clear_color.g, clear_color.b];
+        self.frame_count += 1;
+        if self.keep_frames {
+            self.frames.push(vec![Row::solid(color); self.rows]);
+        }
+    }
+
+    fn handle_events(&mut self, _running: &mut bool, _paused: &mut bool) -> bool {
+        // there's no one to send us any events
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // which of the row's pixels are lit at the given coverage
+    fn lit_columns(layout: CoverageLayout, columns: usize, coverage: f64) -> Vec<usize> {
+        let coverage = (coverage * (Row::FULL as f64)) as u16;
+        layout.thresholds(columns).iter()
+            .enumerate()
+            .filter(|(_, threshold)| coverage > **threshold)
+            .map(|(column, _)| column)
+            .collect()
+    }
+
+    #[test]
+    fn contiguous_coverage() {
+        assert_eq!(lit_columns(CoverageLayout::Contiguous, 8, 0.0), vec![]);
+        assert_eq!(lit_columns(CoverageLayout::Contiguous, 8, 0.25), vec![0, 1]);
+        assert_eq!(lit_columns(CoverageLayout::Contiguous, 8, 1.0), (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn spread_coverage() {
+        assert_eq!(lit_columns(CoverageLayout::Spread, 8, 0.25), vec![0, 4]);
+        assert_eq!(lit_columns(CoverageLayout::Spread, 8, 0.5), vec![0, 2, 4, 6]);
+        // not a power of two
+        assert_eq!(lit_columns(CoverageLayout::Spread, 6, 0.5), vec![0, 2, 4]);
+        assert_eq!(lit_columns(CoverageLayout::Spread, 6, 1.0).len(), 6);
+    }
+
+    #[test]
+    fn carrier_pattern() {
+        let carrier = Carrier {
+            // 4 pixels per cycle, and 10 pixels per row
+            cycles_per_pixel: 0.25,
+            cycles_per_row: 2.5,
+            start_phase: 0.25,
+        };
+        let first_row: Vec<bool> = (0..4).map(|x| carrier.is_high(0, x)).collect();
+        assert_eq!(first_row, vec![true, false, false, true]);
+        // half a cycle later
+        let second_row: Vec<bool> = (0..4).map(|x| carrier.is_high(1, x)).collect();
+        assert_eq!(second_row, vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn average_row_color() {
+        let row = Row {
+            color: [255, 100, 0],
+            coverage: [Row::FULL / 2, Row::FULL, 0],
+        };
+        assert_eq!(row.average_color(), [127, 100, 0]);
+    }
+}
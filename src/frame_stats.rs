@@ -0,0 +1,149 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+// How long frames took to render and present, and how late note changes
+// reached the screen, to tell rendering problems from reception problems.
+
+use std::{fmt::Write, time::Duration};
+
+// Upper bounds of the histogram buckets, in milliseconds.
+// Anything slower goes in a last, unbounded bucket.
+const BUCKET_LIMITS_MS: [f64; 10] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0];
+
+// Width of the fullest histogram bar, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// Durations of one kind of event, e.g. rendering a frame.
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+    durations: Vec<Duration>,
+}
+
+impl Timings {
+    pub fn record(&mut self, duration: Duration) {
+        self.durations.push(duration);
+    }
+
+    pub fn count(&self) -> usize {
+        self.durations.len()
+    }
+
+    /// The duration that `fraction` (between 0 and 1) of them don't exceed.
+    pub fn percentile(&self, fraction: f64) -> Duration {
+        let mut sorted = self.durations.clone();
+        sorted.sort_unstable();
+        let index = ((sorted.len() as f64 - 1.0) * fraction).round() as usize;
+        sorted.get(index).copied().unwrap_or_default()
+    }
+
+    fn mean(&self) -> Duration {
+        let total: Duration = self.durations.iter().sum();
+        total / (self.count().max(1) as u32)
+    }
+
+    // How many durations fall into each bucket.
+    fn histogram(&self) -> [usize; BUCKET_LIMITS_MS.len() + 1] {
+        let mut counts = [0; BUCKET_LIMITS_MS.len() + 1];
+        for duration in &self.durations {
+            let ms = duration.as_secs_f64() * 1000.0;
+            counts[BUCKET_LIMITS_MS.partition_point(|limit| *limit <= ms)] += 1;
+        }
+        counts
+    }
+
+    fn write_summary(&self, name: &str, out: &mut String) {
+        if self.durations.is_empty() {
+            writeln!(out, "{}: none", name).unwrap();
+            return;
+        }
+        writeln!(out, "{}: {} samples, mean {:.2?}, median {:.2?}, \
+                       99th percentile {:.2?}, max {:.2?}",
+                 name, self.count(), self.mean(), self.percentile(0.5),
+                 self.percentile(0.99), self.percentile(1.0)).unwrap();
+        let counts = self.histogram();
+        let max_count = counts.iter().copied().max().unwrap_or(0).max(1);
+        for (index, count) in counts.iter().enumerate() {
+            let label = match BUCKET_LIMITS_MS.get(index) {
+                Some(limit) => format!("< {}ms", limit),
+                None => format!(">= {}ms", BUCKET_LIMITS_MS[index - 1]),
+            };
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max_count));
+            writeln!(out, "  {:>9} {:>7} {}", label, count, bar).unwrap();
+        }
+    }
+}
+
+/// Collected by `Gui` while playing.
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    /// From when each note change was due to when the first frame showing it
+    /// was done being presented.
+    pub note_latency: Timings,
+    /// Computing each frame's rows, or finding them in the cache.
+    pub render_time: Timings,
+    /// Handing each frame to the monitor, which includes waiting for vsync.
+    pub present_time: Timings,
+    /// Frames still being rendered or presented when they should have
+    /// started being scanned, so that the monitor showed an old one instead.
+    pub missed_deadlines: usize,
+    /// Frames the monitor scanned without us ever presenting them.
+    pub skipped_frames: u64,
+    /// Frames of notes looked up in the frame cache.
+    pub cache_lookups: usize,
+    /// Of those, how many were found there, and didn't have to be rendered.
+    pub cache_hits: usize,
+}
+
+impl FrameStats {
+    /// A human-readable summary, with a histogram of each kind of timing.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        self.note_latency.write_summary("note change latency", &mut out);
+        self.render_time.write_summary("render time", &mut out);
+        self.present_time.write_summary("present time", &mut out);
+        writeln!(out, "{} frames presented, {} missed their deadline, {} skipped",
+                 self.render_time.count(), self.missed_deadlines, self.skipped_frames).unwrap();
+        if self.cache_lookups > 0 {
+            writeln!(out, "{} of {} frames of notes found in the cache ({:.1}%)",
+                     self.cache_hits, self.cache_lookups,
+                     100.0 * (self.cache_hits as f64) / (self.cache_lookups as f64)).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timings(ms: &[u64]) -> Timings {
+        let mut timings = Timings::default();
+        for ms in ms {
+            timings.record(Duration::from_millis(*ms));
+        }
+        timings
+    }
+
+    #[test]
+    fn percentiles() {
+        let timings = timings(&[5, 1, 4, 2, 3]);
+        assert_eq!(timings.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(timings.percentile(0.5), Duration::from_millis(3));
+        assert_eq!(timings.percentile(1.0), Duration::from_millis(5));
+        assert_eq!(timings.mean(), Duration::from_millis(3));
+        assert_eq!(Timings::default().percentile(0.5), Duration::ZERO);
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let timings = timings(&[0, 1, 3, 3, 500]);
+        assert_eq!(timings.histogram(), [1, 0, 0, 1, 2, 0, 0, 0, 0, 0, 1]);
+        let mut summary = String::new();
+        timings.write_summary("test", &mut summary);
+        assert!(summary.contains("  >= 128ms       1 ####################\n"), "{}", summary);
+    }
+}
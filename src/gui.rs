@@ -6,229 +6,1353 @@
 // See COPYING.txt.
 
 use std::{
-    f64::consts,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
 };
-use rand::Rng;
-use rand_distr::StandardNormal;
-use sdl2::{
-    EventPump,
-    Sdl,
-    VideoSubsystem,
-    event::{Event, WindowEvent},
-    keyboard::Keycode,
-    pixels::Color,
-    rect::Point,
-    render::WindowCanvas,
+use rand::{
+    SeedableRng,
+    rngs::StdRng,
 };
+use sdl2::pixels::Color;
 
-pub struct Gui {
-    // note: these two are never used directly, but must be held here to ensure
-    // they're not dropped until after the GUI stopped.
-    _sdl_context: Sdl,
-    _video_subsys: VideoSubsystem,
-    canvas: WindowCanvas,
-    event_pump: EventPump,
-    horiz_refresh_rate: f64,
-    res_x: i32,
-    res_y: i32,
-}
-
-impl Gui {
-    pub fn create(horiz_refresh_rate: f64) -> Self {
-        let sdl_context = sdl2::init()
-            .unwrap_or_else(|e| panic!("failed to initialize SDL2: {}", e));
-        let video_subsys = sdl_context.video()
-            .unwrap_or_else(|e| panic!("failed to initialize video subsystem: {}", e));
-
-        let mut window_builder = video_subsys.window("tempest LCD", 0, 0);
-        window_builder.fullscreen_desktop();
-        window_builder.borderless();
-
-        let window = window_builder.build()
-            .unwrap_or_else(|e| panic!("failed to create window: {}", e));
-        let (res_x_uint, res_y_uint) = window.size();
-
-        let res_x: i32 = res_x_uint.try_into()
-            .unwrap_or_else(|e| panic!("failed to convert X resolution {} to i32: {}", res_x_uint, e));
-        let res_y: i32 = res_y_uint.try_into()
-            .unwrap_or_else(|e| panic!("failed to convert Y resolution {} to i32: {}", res_y_uint, e));
-
-        let mut canvas = window.into_canvas()
-            .build()
-            .unwrap_or_else(|e| panic!("failed to make renderer from window: {}", e));
-
-        let event_pump = sdl_context.event_pump()
-            .unwrap_or_else(|e| panic!("failed to get event pump: {}", e));
-
-        clear_and_present(&mut canvas, Color::GRAY);
-
-        Gui {
-            _sdl_context: sdl_context,
-            _video_subsys: video_subsys,
-            canvas,
-            event_pump,
-            horiz_refresh_rate,
-            res_x,
-            res_y,
-        }
-    }
-
-    pub fn draw_single_square_wave(&mut self, note_freq: f64) {
-        self.canvas.set_draw_color(Color::BLACK);
-        self.canvas.clear();
-
-        self.canvas.set_draw_color(Color::WHITE);
-        for y in 0..self.res_y {
-            // approx time when arriving at this row
-            let t = (y as f64) / self.horiz_refresh_rate;
-            // Note that `cosine_is_positive` is true if and only if
-            //      cos(2pi*t*note_freq) > 0
-            //
-            // PROOF: let's abbreviate note_freq to f.
-            // Since t > 0 and f > 0, the cast to i64 works as floor(), thus
-            //      cosine_is_positive <==> floor(2tf) mod 2 == 0
-            //      <==> floor(2tf) == 2n for some integer n
-            //      <==> 2n <= 2tf < 2n + 1
-            //      <==> 2pi*n <= 2pi*tf < 2pi*n + pi
-            // In this interval, for any integer n, `cos` is monotonically
-            // decreasing, and so
-            //      cosine_is_positive
-            //      <==> cos(2pi*n + pi) < cos(2pi*tf) <= cos(2pi*n)
-            //      <==> 0 < cos(2pi*tf) <= 1                           QED
-            //
-            let cosine_is_positive = ((2.0 * t * note_freq) as i64) % 2 == 0;
-            if cosine_is_positive {
-                let origin = Point::new(0, y);
-                let dest = Point::new(self.res_x, y);
-                self.canvas.draw_line(origin, dest)
-                    .unwrap_or_else(|e| panic!("failed to draw line: {}", e));
+use crate::{
+    calibration::Calibration,
+    encoding::{self, Dither, Encoding},
+    frame_cache::FrameCache,
+    frame_stats::FrameStats,
+    frame_sink::{Carrier, FrameSink, Row, RowPattern},
+    timing::ScanTiming,
+    waveform::{WaveShape, Waveform},
+    worker_pool::WorkerPool,
+};
+
+// When using the frame cache, each voice's phase at the start of a frame is
+// rounded to how far it goes in 1/CACHE_STEPS_PER_ROW of a row, so that frames
+// are only off by a fraction of a row, whatever the note's frequency.
+const CACHE_STEPS_PER_ROW: f64 = 4.0;
+
+/// A single note being played.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Voice {
+    /// In Hz.
+    pub freq: f64,
+    /// Fraction of each cycle where the wave is high, between 0 and 1.
+    /// Only used by square (pulse) waves.
+    pub duty: f64,
+    /// Loudness relative to the other voices played at the same time.
+    /// Must be positive.
+    pub amplitude: f64,
+}
+
+/// What is currently being broadcast.
+/// Kept around so the same signal can be rendered again on every frame.
+enum Signal {
+    Blank(Color),
+    Tones {
+        waveform: Waveform,
+        voices: Vec<Voice>,
+        // broadcast time (in seconds) at which each voice's phase was 0,
+        // i.e. the start of the first frame it was shown on, so that a note
+        // looks the same every time it's played, and can come from the cache.
+        // Filled in by `set_signal`.
+        onsets: Vec<f64>,
+    },
+}
+
+/// Identifies a frame in the frame cache:
+/// the same notes, starting at the same phases, look the same.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct FrameKey {
+    // rows differ between screens with different timings
+    line_rate: u64,
+    rows: usize,
+    waveform: String,
+    voices: Vec<VoiceKey>,
+}
+
+// f64 isn't hashable, so use their bits instead
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct VoiceKey {
+    freq: u64,
+    duty: u64,
+    amplitude: u64,
+    phase_step: u32,
+}
+
+/// Which of each pixel's sub-pixels (red, green and blue) carry the signal.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorMode {
+    /// All three, equally.
+    #[default]
+    Gray,
+    /// Each one with the given brightness (between 0 and 1) relative to
+    /// the signal, in R, G, B order: e.g. `[1.0, 0.0, 0.0]` for red only.
+    Weighted([f64; 3]),
+    /// Each voice on a single channel: the first voice on red,
+    /// the second on green, the third on blue, the fourth on red again,
+    /// and so on.
+    SplitVoices,
+}
+
+impl ColorMode {
+    /// Parse `gray`, `red`, `green`, `blue`, `split`,
+    /// or comma-separated weights such as `1,0.5,0`.
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "gray" => Ok(ColorMode::Gray),
+            "red" => Ok(ColorMode::Weighted([1.0, 0.0, 0.0])),
+            "green" => Ok(ColorMode::Weighted([0.0, 1.0, 0.0])),
+            "blue" => Ok(ColorMode::Weighted([0.0, 0.0, 1.0])),
+            "split" => Ok(ColorMode::SplitVoices),
+            _ => {
+                let weights = mode.split(',')
+                    .map(|weight| weight.trim().parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()
+                    .map_err(|e| format!("bad color weights '{}': {}", mode, e))?;
+                let Ok(weights) = <[f64; 3]>::try_from(weights) else {
+                    return Err(format!(
+                            "color mode '{}' should be gray, red, green, blue, split or R,G,B",
+                            mode));
+                };
+                if weights.iter().any(|weight| !(0.0..=1.0).contains(weight)) {
+                    return Err(format!("color weights '{}' must be between 0 and 1", mode));
+                }
+                Ok(ColorMode::Weighted(weights))
+            },
+        }
+    }
+}
+
+/// Settings that change how notes are rendered,
+/// as opposed to which notes are rendered.
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// Paint the rows where a square wave goes up or down with a gray level
+    /// proportional to how long the wave was high while the row was scanned,
+    /// instead of only looking at the start of the row.
+    pub antialias: bool,
+    pub encoding: Encoding,
+    /// Dithering used for cosine waves.
+    pub dither: Dither,
+    /// Seed for the dithering noise, so frames can be reproduced.
+    /// If `None`, a random one is used.
+    pub seed: Option<u64>,
+    /// How the monitor's emission depends on the gray level.
+    /// If `None`, they're assumed to be proportional.
+    pub calibration: Option<Calibration>,
+    pub color: ColorMode,
+    /// How far the signal swings, as a fraction of the range between black
+    /// and white: 1 goes from black to white, while lower values keep the
+    /// carrier from ever being fully off.
+    pub depth: f64,
+    /// The level the signal swings around, between 0 (black) and 1 (white).
+    pub offset: f64,
+    /// Frequency (in Hz) of a carrier to draw along each row, for monitors
+    /// that scan pixels one at a time, such as CRTs. Requires the pixel clock.
+    /// If `None`, every pixel in a row is painted the same.
+    pub crt_carrier: Option<f64>,
+    /// Most times per second the signal may change (e.g. from one note to
+    /// the next); later changes are held back until enough time has passed.
+    /// If `None`, changes are shown right away.
+    pub max_change_rate: Option<f64>,
+    /// Reduce the contrast and how often the signal changes to (at most)
+    /// `SAFE_DEPTH` and `SAFE_CHANGE_RATE`, so the screen doesn't flash.
+    pub safe_mode: bool,
+}
+
+/// Modulation depth used in safe mode.
+pub const SAFE_DEPTH: f64 = 0.25;
+
+/// Changes per second allowed in safe mode, below the three flashes
+/// per second that photosensitivity guidelines warn about.
+pub const SAFE_CHANGE_RATE: f64 = 3.0;
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            antialias: false,
+            encoding: Encoding::default(),
+            dither: Dither::default(),
+            seed: None,
+            calibration: None,
+            color: ColorMode::default(),
+            depth: 1.0,
+            offset: 0.5,
+            crt_carrier: None,
+            max_change_rate: None,
+            safe_mode: false,
+        }
+    }
+}
+
+pub struct Gui<S: FrameSink> {
+    screens: Vec<Screen<S>>,
+    options: RenderOptions,
+    // for dithering
+    rng: StdRng,
+    // computes the rows' levels
+    pool: WorkerPool,
+    frame_cache: FrameCache<FrameKey, Row>,
+    // broadcast time is measured from here, on every screen
+    start: Instant,
+    stats: FrameStats,
+}
+
+// A sink with its own timing, broadcasting its own signal.
+struct Screen<S: FrameSink> {
+    sink: S,
+    // rows scanned per second
+    line_rate: f64,
+    // rows scanned per frame, including vertical blanking
+    total_lines: usize,
+    // pixels scanned per second, if known
+    pixel_clock: Option<f64>,
+    // desired level of each row, between 0 and 1, reused across frames
+    levels: Vec<f64>,
+    // how to paint each row, reused across frames
+    rows: Vec<Row>,
+    signal: Signal,
+    // a change held back because of `max_change_rate`,
+    // and when it was due, if it's a note change
+    pending_signal: Option<Signal>,
+    pending_due: Option<Instant>,
+    last_signal_change: Option<Instant>,
+    last_frame_index: Option<u64>,
+    // when the last frame was done being handed to the sink
+    last_present_end: Option<Instant>,
+}
+
+impl<S: FrameSink> Screen<S> {
+    // If `timing` is `None`, try to detect it from the sink.
+    fn create(sink: S, timing: Option<ScanTiming>, options: &RenderOptions) -> Self {
+        let timing = timing
+            .or_else(|| sink.detect_scan_timing())
+            .expect("failed to detect the horizontal refresh rate: please specify it");
+        let rows = vec![Row::solid([0; 3]); sink.rows()];
+        let total_lines = match timing.total_lines {
+            Some(total_lines) => total_lines as usize,
+            None => rows.len(),
+        };
+        if total_lines < rows.len() {
+            panic!("display timing has {} rows per frame, but the screen has {} visible rows",
+                   total_lines, rows.len());
+        }
+        if let Some(carrier) = options.crt_carrier {
+            let Some(pixel_clock) = timing.pixel_clock else {
+                panic!("drawing a carrier requires the pixel clock: \
+                        please specify the full display timing");
+            };
+            // otherwise, there wouldn't be a pixel for each half cycle
+            if carrier > pixel_clock / 2.0 {
+                panic!("carrier frequency ({}Hz) must be at most half the pixel clock ({}Hz)",
+                       carrier, pixel_clock);
             }
         }
-        self.canvas.present();
-    }
-
-    pub fn draw_single_cosine_wave(&mut self, note_freq: f64) {
-        self.canvas.set_draw_color(Color::BLACK);
-        self.canvas.clear();
-        for y in 0..self.res_y {
-            // approx time when arriving at this row
-            let t = (y as f64) / self.horiz_refresh_rate;
-            let dither: f64 = rand::thread_rng().sample(StandardNormal);
-            // note: TAU = 2 * PI
-            let raw_ampl = (consts::TAU * t * note_freq).cos();
-            let color_component = (127.5 * (1.0 + raw_ampl) + dither) as u8;
-            let color = Color::RGB(color_component,
-                                   color_component,
-                                   color_component);
-            self.canvas.set_draw_color(color);
-            let origin = Point::new(0, y);
-            let dest = Point::new(self.res_x, y);
-            self.canvas.draw_line(origin, dest)
-                .unwrap_or_else(|e| panic!("failed to draw line: {}", e));
-        }
-        self.canvas.present();
-    }
-
-    pub fn draw_square_waves(&mut self, freqs: &[f64]) {
-        self.canvas.set_draw_color(Color::BLACK);
-        self.canvas.clear();
-
-        for y in 0..self.res_y {
-            // approx time when arriving at this row
-            let t = (y as f64) / self.horiz_refresh_rate;
-            let mut level : i32 = 0;
-            for note_freq in freqs {
-                // Same logic as in `draw_single_square_wave`
-                let cosine_is_positive = ((2.0 * t * note_freq) as i64) % 2 == 0;
-                if cosine_is_positive {
-                    level += 1;
-                } else {
-                    level -= 1;
+        Screen {
+            sink,
+            line_rate: timing.line_rate,
+            total_lines,
+            pixel_clock: timing.pixel_clock,
+            levels: vec![0.0; rows.len()],
+            rows,
+            signal: Signal::Blank(Color::GRAY),
+            pending_signal: None,
+            pending_due: None,
+            last_signal_change: None,
+            last_frame_index: None,
+            last_present_end: None,
+        }
+    }
+
+    // time the monitor takes to scan an entire frame,
+    // including the vertical blanking interval after the last visible row
+    fn frame_period(&self) -> f64 {
+        (self.total_lines as f64) / self.line_rate
+    }
+
+    // When each of the given voices started, if they're shown from
+    // `frame_start` on: notes that were already playing keep going from
+    // where they were, and new ones start then, at phase 0.
+    fn onsets(&self, voices: &[Voice], frame_start: f64) -> Vec<f64> {
+        let (old_voices, old_onsets) = match &self.signal {
+            Signal::Tones { voices, onsets, .. } => (voices.as_slice(), onsets.as_slice()),
+            Signal::Blank(_) => (&[][..], &[][..]),
+        };
+        voices.iter()
+            .map(|voice| {
+                old_voices.iter()
+                    .zip(old_onsets)
+                    .find(|(old_voice, _)| old_voice.freq == voice.freq)
+                    .map_or(frame_start, |(_, onset)| *onset)
+            })
+            .collect()
+    }
+
+    // whether enough time has passed since the last change,
+    // according to `max_change_rate`
+    fn can_change_signal(&self, max_change_rate: Option<f64>) -> bool {
+        match (max_change_rate, self.last_signal_change) {
+            (Some(rate), Some(last_change)) => last_change.elapsed().as_secs_f64() >= 1.0 / rate,
+            _ => true,
+        }
+    }
+}
+
+impl<S: FrameSink> Gui<S> {
+    /// Broadcast on each of the given sinks, e.g. one per monitor,
+    /// with its own timing (detected from the sink if `None`).
+    /// Screens are numbered in the given order.
+    pub fn create(sinks: Vec<(S, Option<ScanTiming>)>,
+                  frame_cache: FrameCache<FrameKey, Row>,
+                  mut options: RenderOptions) -> Self {
+        assert!(!sinks.is_empty(), "there must be at least one screen");
+        if options.safe_mode {
+            options.depth = options.depth.min(SAFE_DEPTH);
+            options.max_change_rate = Some(options.max_change_rate
+                .map_or(SAFE_CHANGE_RATE, |rate| rate.min(SAFE_CHANGE_RATE)));
+        }
+        let screens = sinks.into_iter()
+            .map(|(sink, timing)| Screen::create(sink, timing, &options))
+            .collect();
+        let mut gui = Gui {
+            screens,
+            rng: match options.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            pool: WorkerPool::new(thread::available_parallelism()
+                                  .map_or(1, |threads| threads.get())),
+            frame_cache,
+            start: Instant::now(),
+            stats: FrameStats::default(),
+            options,
+        };
+        for screen in 0..gui.screens.len() {
+            let frame_index = gui.next_frame_index(screen);
+            gui.present_frame(screen, frame_index);
+        }
+        gui
+    }
+
+    /// The first screen's sink.
+    pub fn sink(&self) -> &S {
+        &self.screens[0].sink
+    }
+
+    pub fn screen_count(&self) -> usize {
+        self.screens.len()
+    }
+
+    /// The notes being played on the given screen, in the order they were
+    /// given (none if it's blank).
+    #[cfg(test)]
+    pub fn voices_on(&self, screen: usize) -> &[Voice] {
+        match &self.screens[screen].signal {
+            Signal::Blank(_) => &[],
+            Signal::Tones { voices, .. } => voices,
+        }
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    /// Record how late the last drawn notes are on every screen.
+    pub fn record_note_change(&mut self, due: Instant) {
+        for screen in 0..self.screens.len() {
+            self.record_note_change_on(screen, due);
+        }
+    }
+
+    /// Record how late the last notes drawn on the given screen are,
+    /// compared to when they were due: they're only seen once their
+    /// first frame is presented.
+    pub fn record_note_change_on(&mut self, screen: usize, due: Instant) {
+        let screen = &mut self.screens[screen];
+        if screen.pending_signal.is_some() {
+            // recorded once it's shown
+            screen.pending_due = Some(due);
+            return;
+        }
+        let Some(present_end) = screen.last_present_end else {
+            return;
+        };
+        self.stats.note_latency.record(present_end.saturating_duration_since(due));
+    }
+
+    /// Draw a note on every screen.
+    pub fn draw_single_wave(&mut self, waveform: &Waveform, voice: Voice) {
+        self.draw_waves(waveform, &[voice]);
+    }
+
+    /// Draw notes on every screen.
+    pub fn draw_waves(&mut self, waveform: &Waveform, voices: &[Voice]) {
+        for screen in 0..self.screens.len() {
+            self.draw_waves_on(screen, waveform, voices);
+        }
+    }
+
+    pub fn draw_waves_on(&mut self, screen: usize, waveform: &Waveform, voices: &[Voice]) {
+        self.set_signal(screen, Signal::Tones {
+            waveform: waveform.clone(),
+            voices: voices.to_vec(),
+            onsets: Vec::new(),
+        });
+    }
+
+    /// Fill every screen with a single color.
+    pub fn clear_and_present(&mut self, clear_color: Color) {
+        for screen in 0..self.screens.len() {
+            self.set_signal(screen, Signal::Blank(clear_color));
+        }
+    }
+
+    /// Render the current signal again on each screen that is about to
+    /// show a frame we haven't rendered yet, so that held notes stay in
+    /// phase from one frame to the next. Must be called often while playing.
+    pub fn refresh(&mut self) {
+        for screen in 0..self.screens.len() {
+            self.refresh_screen(screen);
+        }
+    }
+
+    fn refresh_screen(&mut self, screen: usize) {
+        if self.screens[screen].can_change_signal(self.options.max_change_rate) {
+            if let Some(signal) = self.screens[screen].pending_signal.take() {
+                self.set_signal(screen, signal);
+                if let Some(due) = self.screens[screen].pending_due.take() {
+                    self.record_note_change_on(screen, due);
                 }
+                return;
             }
-            let level_norm = ((level as f64) + (freqs.len() as f64)) / (freqs.len() as f64);
-            let color_comp = (level_norm * 127.5) as u8;
-            let color = Color::RGB(color_comp, color_comp, color_comp);
-            self.canvas.set_draw_color(color);
-            let origin = Point::new(0, y);
-            let dest = Point::new(self.res_x, y);
-            self.canvas.draw_line(origin, dest)
-                .unwrap_or_else(|e| panic!("failed to draw line: {}", e));
-
-        }
-        self.canvas.present();
-    }
-
-    pub fn draw_cosine_waves(&mut self, freqs: &[f64]) {
-        self.canvas.set_draw_color(Color::BLACK);
-        self.canvas.clear();
-        for y in 0..self.res_y {
-            // approx time when arriving at this row
-            let t = (y as f64) / self.horiz_refresh_rate;
-            let mut raw_ampl = 0.0;
-            let dither: f64 = rand::thread_rng().sample(StandardNormal);
-            for note_freq in freqs {
-                // note: TAU = 2 * PI
-                raw_ampl += (consts::TAU * t * note_freq).cos();
+        }
+        let frame_index = self.next_frame_index(screen);
+        if self.screens[screen].last_frame_index != Some(frame_index) {
+            self.present_frame(screen, frame_index);
+        }
+    }
+
+    fn set_signal(&mut self, screen: usize, signal: Signal) {
+        let max_change_rate = self.options.max_change_rate;
+        let screen_state = &mut self.screens[screen];
+        if !screen_state.can_change_signal(max_change_rate) {
+            // only the latest change is shown once it's allowed
+            screen_state.pending_signal = Some(signal);
+            screen_state.pending_due = None;
+            return;
+        }
+        let frame_index = self.next_frame_index(screen);
+        let screen_state = &mut self.screens[screen];
+        let frame_start = (frame_index as f64) * screen_state.frame_period();
+        let signal = match signal {
+            Signal::Tones { waveform, voices, .. } => {
+                let onsets = screen_state.onsets(&voices, frame_start);
+                Signal::Tones { waveform, voices, onsets }
+            },
+            blank => blank,
+        };
+        screen_state.pending_signal = None;
+        screen_state.signal = signal;
+        screen_state.last_signal_change = Some(Instant::now());
+        self.present_frame(screen, frame_index);
+    }
+
+    // index of the first frame that will be scanned on the given screen
+    // after now, counting from when the GUI was created
+    fn next_frame_index(&self, screen: usize) -> u64 {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        (elapsed / self.screens[screen].frame_period()) as u64 + 1
+    }
+
+    fn present_frame(&mut self, screen: usize, frame_index: u64) {
+        let screen_state = &mut self.screens[screen];
+        if let Some(last_frame_index) = screen_state.last_frame_index {
+            self.stats.skipped_frames += frame_index.saturating_sub(last_frame_index + 1);
+        }
+        screen_state.last_frame_index = Some(frame_index);
+        let render_start = Instant::now();
+        let (waveform, voices, onsets) = match &screen_state.signal {
+            Signal::Blank(color) => {
+                let color = *color;
+                let color = self.reduce_contrast(color);
+                self.screens[screen].sink.clear_and_present(color);
+                self.record_frame(screen, frame_index, render_start, render_start);
+                return;
+            },
+            // copied so the frame can be rendered by methods borrowing `self`
+            Signal::Tones { waveform, voices, onsets } => {
+                (waveform.clone(), voices.clone(), onsets.clone())
+            },
+        };
+        // broadcast time at which the frame's first row is scanned
+        let frame_start = (frame_index as f64) * screen_state.frame_period();
+        let pattern = self.row_pattern(screen, frame_start);
+        // phase of each note at that time, in cycles
+        let mut start_phases: Vec<f64> = voices.iter()
+            .zip(&onsets)
+            .map(|(voice, onset)| ((frame_start - onset) * voice.freq).rem_euclid(1.0))
+            .collect();
+
+        let mut frame_key = None;
+        if self.frame_cache.is_enabled() {
+            let line_rate = self.screens[screen].line_rate;
+            // render with the rounded phases, so the frame is the same
+            // whether it came from the cache or not
+            let phase_steps: Vec<u32> = start_phases.iter_mut()
+                .zip(&voices)
+                .map(|(phase, voice)| {
+                    let (step, rounded_phase) = round_phase(*phase, voice.freq, line_rate);
+                    *phase = rounded_phase;
+                    step
+                })
+                .collect();
+            let mut voice_keys: Vec<VoiceKey> = voices.iter()
+                .zip(phase_steps)
+                .map(|(voice, phase_step)| VoiceKey {
+                    freq: voice.freq.to_bits(),
+                    duty: voice.duty.to_bits(),
+                    amplitude: voice.amplitude.to_bits(),
+                    phase_step,
+                })
+                .collect();
+            // the order of the notes doesn't matter,
+            // unless it decides which channel they're played on
+            if self.options.color != ColorMode::SplitVoices {
+                voice_keys.sort_unstable();
+            }
+            let screen_state = &mut self.screens[screen];
+            let key = FrameKey {
+                line_rate: screen_state.line_rate.to_bits(),
+                rows: screen_state.rows.len(),
+                waveform: waveform.name(),
+                voices: voice_keys,
+            };
+            self.stats.cache_lookups += 1;
+            if let Some(rows) = self.frame_cache.get(&key) {
+                self.stats.cache_hits += 1;
+                let present_start = Instant::now();
+                screen_state.sink.present_rows(rows, pattern);
+                self.record_frame(screen, frame_index, render_start, present_start);
+                return;
             }
-            let ampl_norm = (raw_ampl + (freqs.len() as f64)) / (freqs.len() as f64);
-            let color_component = (127.5 * ampl_norm + dither) as u8;
-            let color = Color::RGB(color_component,
-                                   color_component,
-                                   color_component);
-            self.canvas.set_draw_color(color);
-            let origin = Point::new(0, y);
-            let dest = Point::new(self.res_x, y);
-            self.canvas.draw_line(origin, dest)
-                .unwrap_or_else(|e| panic!("failed to draw line: {}", e));
+            frame_key = Some(key);
+        }
+
+        self.render_rows(screen, &waveform, &voices, &start_phases);
+        let screen_state = &mut self.screens[screen];
+        if let Some(key) = frame_key {
+            self.frame_cache.insert(key, &screen_state.rows);
+        }
+        let present_start = Instant::now();
+        screen_state.sink.present_rows(&screen_state.rows, pattern);
+        self.record_frame(screen, frame_index, render_start, present_start);
+    }
+
+    // Apply the modulation depth and offset to a color, as they are to
+    // the notes' levels, so that rests don't swing further than the signal.
+    // With the default depth and offset, the color is unchanged.
+    fn reduce_contrast(&self, color: Color) -> Color {
+        let (depth, offset) = (self.options.depth, self.options.offset);
+        let reduce = |channel: u8| {
+            let level = offset + depth * ((channel as f64) / 255.0 - 0.5);
+            (255.0 * level.clamp(0.0, 1.0)).round() as u8
+        };
+        Color::RGB(reduce(color.r), reduce(color.g), reduce(color.b))
+    }
+
+    fn record_frame(&mut self,
+                    screen: usize,
+                    frame_index: u64,
+                    render_start: Instant,
+                    present_start: Instant) {
+        let present_end = Instant::now();
+        let render_time = present_start - render_start;
+        let present_time = present_end - present_start;
+        let screen = &mut self.screens[screen];
+        screen.last_present_end = Some(present_end);
+        // Presenting may wait for vsync, i.e. for the previous frame to be
+        // scanned, so that isn't late: only still presenting when the frame
+        // should have started being scanned is.
+        let frame_start = self.start
+            + Duration::from_secs_f64((frame_index as f64) * screen.frame_period());
+        if present_end > frame_start {
+            self.stats.missed_deadlines += 1;
         }
-        self.canvas.present();
+        self.stats.render_time.record(render_time);
+        self.stats.present_time.record(present_time);
     }
 
+    // How to lay out the pixels of each row, for a frame starting at the
+    // given broadcast time on the given screen.
+    fn row_pattern(&self, screen: usize, frame_start: f64) -> RowPattern {
+        let screen = &self.screens[screen];
+        if let (Some(carrier), Some(pixel_clock)) = (self.options.crt_carrier, screen.pixel_clock) {
+            return RowPattern::Carrier(Carrier {
+                cycles_per_pixel: carrier / pixel_clock,
+                cycles_per_row: carrier / screen.line_rate,
+                start_phase: (frame_start * carrier).rem_euclid(1.0),
+            });
+        }
+        match self.options.encoding {
+            Encoding::Width(layout) => RowPattern::Coverage(layout),
+            _ => RowPattern::default(),
+        }
+    }
+
+    // Fill the given screen's rows with the given notes,
+    // according to the color mode.
+    fn render_rows(&mut self,
+                   screen: usize,
+                   waveform: &Waveform,
+                   voices: &[Voice],
+                   start_phases: &[f64]) {
+        match self.options.color {
+            ColorMode::Gray => {
+                self.render_channel(screen, 0, waveform, voices, start_phases, 1.0);
+                for row in self.screens[screen].rows.iter_mut() {
+                    row.color = [row.color[0]; 3];
+                    row.coverage = [row.coverage[0]; 3];
+                }
+            },
+            ColorMode::Weighted(weights) => {
+                for (channel_index, gain) in weights.into_iter().enumerate() {
+                    self.render_into_rows(screen, channel_index, waveform, voices, start_phases,
+                                          gain);
+                }
+            },
+            ColorMode::SplitVoices => {
+                for channel_index in 0..3 {
+                    let (channel_voices, channel_phases): (Vec<Voice>, Vec<f64>) = voices.iter()
+                        .zip(start_phases)
+                        .skip(channel_index)
+                        .step_by(3)
+                        .unzip();
+                    self.render_into_rows(screen,
+                                          channel_index,
+                                          waveform,
+                                          &channel_voices,
+                                          &channel_phases,
+                                          1.0);
+                }
+            },
+        }
+    }
+
+    // Render the given notes into a single channel (0 for red, 1 for green,
+    // 2 for blue) of the screen's rows. With no notes, the channel sits at the
+    // offset (times the gain), as silence does in gray; with no gain, it's dark.
+    fn render_into_rows(&mut self,
+                        screen: usize,
+                        channel_index: usize,
+                        waveform: &Waveform,
+                        voices: &[Voice],
+                        start_phases: &[f64],
+                        gain: f64) {
+        if gain == 0.0 {
+            for row in self.screens[screen].rows.iter_mut() {
+                row.color[channel_index] = 0;
+                row.coverage[channel_index] = Row::FULL;
+            }
+        } else {
+            self.render_channel(screen, channel_index, waveform, voices, start_phases, gain);
+        }
+    }
+
+    // Paint the given notes into a single channel of the screen's rows,
+    // with the modulation depth and offset applied, and their brightness
+    // multiplied by `gain`.
+    fn render_channel(&mut self,
+                      screen: usize,
+                      channel_index: usize,
+                      waveform: &Waveform,
+                      voices: &[Voice],
+                      start_phases: &[f64],
+                      gain: f64) {
+        let screen = &mut self.screens[screen];
+        render_tones_parallel(&self.pool,
+                              waveform,
+                              voices,
+                              start_phases,
+                              screen.line_rate,
+                              self.options.antialias,
+                              &mut screen.levels);
+        let (depth, offset) = (self.options.depth, self.options.offset);
+        if (depth, offset) != (1.0, 0.5) {
+            for level in screen.levels.iter_mut() {
+                *level = offset + depth * (*level - 0.5);
+            }
+        }
+        if gain != 1.0 {
+            for level in screen.levels.iter_mut() {
+                *level *= gain;
+            }
+        }
+        // most waves need intermediate gray levels (as do antialiased edges),
+        // whose quantization error would otherwise be audible
+        let dither = if waveform.is_two_level() && !self.options.antialias {
+            Dither::None
+        } else {
+            self.options.dither
+        };
+        let extremes = (gain * (offset - depth / 2.0), gain * (offset + depth / 2.0));
+        encoding::encode(self.options.encoding,
+                         dither,
+                         self.options.calibration.as_ref(),
+                         extremes,
+                         &screen.levels,
+                         &mut screen.rows,
+                         channel_index,
+                         &mut self.rng);
+    }
 
+    /// Handle the events of every screen. Pausing blanks all of them,
+    /// as a rest would be.
     pub fn handle_events(&mut self, running: &mut bool, paused: &mut bool) {
-        for ev in self.event_pump.poll_iter() {
-            match ev {
-                Event::Quit {..} => *running = false,
-                Event::Window { win_event, .. } => match win_event {
-                    WindowEvent::Close => *running = false,
-                    WindowEvent::FocusLost => {
-                        clear_and_present(&mut self.canvas, Color::BLACK);
-                        *paused = true;
-                    },
-                    WindowEvent::Shown
-                        | WindowEvent::Exposed
-                        | WindowEvent::FocusGained => {
-                            clear_and_present(&mut self.canvas, Color::BLACK);
-                    },
-                    _ => {},
-                },
-                Event::KeyDown { keycode: Some(key), .. } => match key {
-                    Keycode::Q => *running = false,
-                    Keycode::P | Keycode::Space => {
-                        if !*paused {
-                            clear_and_present(&mut self.canvas, Color::BLACK);
-                            *paused = true;
-                        } else {
-                            *paused = false;
-                            // since we don't have access to the notes,
-                            // let the player re-render them.
-                        }
-                    },
-                    _ => {},
-                },
-                _ => {},
+        let was_paused = *paused;
+        // one sink may get another's events, so any exposure repaints them all
+        let mut exposed = false;
+        for screen in self.screens.iter_mut() {
+            exposed |= screen.sink.handle_events(running, paused);
+        }
+        if !*paused {
+            // anything lost is presented again on the next refresh
+            return;
+        }
+        for screen in 0..self.screens.len() {
+            let can_change = self.screens[screen].can_change_signal(self.options.max_change_rate);
+            if !was_paused {
+                self.set_signal(screen, Signal::Blank(Color::BLACK));
+            } else if can_change && self.screens[screen].pending_signal.is_some() {
+                // the blank was held back until now
+                let signal = self.screens[screen].pending_signal.take().unwrap();
+                self.set_signal(screen, signal);
+            } else if exposed {
+                let frame_index = self.next_frame_index(screen);
+                self.present_frame(screen, frame_index);
             }
+            // frames aren't skipped while paused, just not shown
+            self.screens[screen].last_frame_index = None;
         }
     }
+}
 
-    pub fn clear_and_present(&mut self, clear_color: Color) {
-        clear_and_present(&mut self.canvas, clear_color);
+// Round the phase of a voice with the given frequency to a multiple of how far
+// it goes in 1/CACHE_STEPS_PER_ROW of a row.
+// Returns which multiple it is, and the rounded phase.
+fn round_phase(phase: f64, freq: f64, line_rate: f64) -> (u32, f64) {
+    let step_cycles = freq / (line_rate * CACHE_STEPS_PER_ROW);
+    let step = (phase / step_cycles).round();
+    (step as u32, step * step_cycles)
+}
+
+// Below this many waves evaluated per frame, handing them to other threads
+// takes longer than evaluating them.
+const MIN_PARALLEL_WORK: usize = 32 * 1024;
+
+// Same as `render_tones`, but splitting the rows among the pool's threads.
+// Each row is computed the same way regardless, so the result is the same.
+fn render_tones_parallel(pool: &WorkerPool,
+                         waveform: &Waveform,
+                         voices: &[Voice],
+                         start_phases: &[f64],
+                         line_rate: f64,
+                         antialias: bool,
+                         levels: &mut [f64]) {
+    if pool.threads() <= 1 || levels.len() * voices.len() < MIN_PARALLEL_WORK {
+        render_tones(waveform.as_ref(), voices, start_phases, line_rate, antialias, 0, levels);
+        return;
+    }
+    let chunk_rows = levels.len().div_ceil(pool.threads());
+    let voices: Arc<[Voice]> = voices.into();
+    let start_phases: Arc<[f64]> = start_phases.into();
+    let jobs: Vec<_> = levels.chunks(chunk_rows)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let (waveform, voices, start_phases) =
+                (waveform.clone(), voices.clone(), start_phases.clone());
+            let rows = chunk.len();
+            move || {
+                let mut chunk = vec![0.0; rows];
+                render_tones(waveform.as_ref(), &voices, &start_phases, line_rate, antialias,
+                             index * chunk_rows, &mut chunk);
+                chunk
+            }
+        })
+        .collect();
+    for (levels, chunk) in levels.chunks_mut(chunk_rows).zip(pool.run(jobs)) {
+        levels.copy_from_slice(&chunk);
+    }
+}
+
+// Fill `levels` with the sum of the given notes, weighted by their amplitudes,
+// each starting at the given phase (in cycles) on the first row.
+// `levels` holds the rows starting at `first_row`.
+// The sum is scaled to the range [0, 1], with silence in the middle.
+fn render_tones(waveform: &dyn WaveShape,
+                voices: &[Voice],
+                start_phases: &[f64],
+                line_rate: f64,
+                antialias: bool,
+                first_row: usize,
+                levels: &mut [f64]) {
+    let total_ampl: f64 = voices.iter().map(|voice| voice.amplitude).sum();
+    if total_ampl == 0.0 {
+        // no notes (or only silent ones), which would divide by zero below
+        levels.fill(0.5);
+        return;
+    }
+    // how far each voice's phase goes from one row to the next
+    let row_cycles: Vec<f64> = voices.iter().map(|voice| voice.freq / line_rate).collect();
+    // one voice at a time, adding them up in the same order on every row
+    levels.fill(0.0);
+    for ((voice, start_phase), row_cycles) in voices.iter().zip(start_phases).zip(&row_cycles) {
+        for (y, level) in (first_row..).zip(levels.iter_mut()) {
+            let phase = start_phase + (y as f64) * row_cycles;
+            let value = if antialias {
+                // the wave's average over the time the row is scanned
+                waveform.average(phase, phase + row_cycles, voice.duty)
+            } else {
+                waveform.value(phase, voice.duty)
+            };
+            *level += voice.amplitude * value;
+        }
+    }
+    for level in levels.iter_mut() {
+        let level_norm = (*level + total_ampl) / total_ampl;
+        *level = level_norm / 2.0;
     }
 }
 
-fn clear_and_present(canvas: &mut WindowCanvas, clear_color: Color) {
-    canvas.set_draw_color(clear_color);
-    canvas.clear();
-    canvas.present();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::{
+        frame_sink::MemorySink,
+        timing::DisplayTiming,
+        waveform::{Cosine, Sawtooth, Square},
+    };
+
+    fn square() -> Waveform {
+        Arc::new(Square)
+    }
+
+    fn cosine() -> Waveform {
+        Arc::new(Cosine)
+    }
+
+    // a note with default duty and amplitude
+    fn voice(freq: f64) -> Voice {
+        Voice {
+            freq,
+            duty: 0.5,
+            amplitude: 1.0,
+        }
+    }
+
+    // 1000 rows per second, without blanking nor cache
+    fn create_test_gui(rows: usize) -> Gui<MemorySink> {
+        let timing = ScanTiming::from_line_rate(1000.0);
+        Gui::create(vec![(MemorySink::new(rows), Some(timing))],
+                    FrameCache::new(0),
+                    RenderOptions::default())
+    }
+
+    #[test]
+    fn starts_with_gray_frame() {
+        let gui = create_test_gui(4);
+        assert_eq!(gui.sink().frames(), &[vec![128; 4]]);
+    }
+
+    #[test]
+    fn single_square_wave_rows() {
+        let mut gui = create_test_gui(8);
+        // a 250Hz wave changes sign every 2 rows at 1000 rows per second
+        gui.draw_single_wave(&square(), voice(250.0));
+        assert_eq!(gui.sink().frames()[1], vec![255, 255, 0, 0, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn held_note_is_phase_continuous() {
+        let mut gui = create_test_gui(4);
+        // a 125Hz wave changes sign every 4 rows, i.e. every frame
+        gui.screens[0].signal = Signal::Tones {
+            waveform: square(),
+            voices: vec![voice(125.0)],
+            onsets: vec![0.0],
+        };
+        gui.present_frame(0, 1);
+        gui.present_frame(0, 2);
+        gui.present_frame(0, 3);
+        assert_eq!(&gui.sink().frames()[1..], &[
+            vec![0, 0, 0, 0],
+            vec![255, 255, 255, 255],
+            vec![0, 0, 0, 0],
+        ]);
+    }
+
+    #[test]
+    fn blanking_is_accounted_for() {
+        let timing = ScanTiming {
+            line_rate: 1000.0,
+            total_lines: Some(8),
+            pixel_clock: None,
+        };
+        let mut gui = Gui::create(vec![(MemorySink::new(4), Some(timing))],
+                                  FrameCache::new(0),
+                                  RenderOptions::default());
+        // a 125Hz wave changes sign every 4 rows, and each frame takes 8
+        gui.screens[0].signal = Signal::Tones {
+            waveform: square(),
+            voices: vec![voice(125.0)],
+            onsets: vec![0.0],
+        };
+        gui.present_frame(0, 1);
+        assert_eq!(gui.sink().frames()[1], vec![255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn square_wave_duty_cycle() {
+        let mut gui = create_test_gui(8);
+        // a 125Hz wave lasts for 8 rows
+        gui.draw_single_wave(&square(), Voice { duty: 0.25, ..voice(125.0) });
+        assert_eq!(gui.sink().frames()[1], vec![255, 255, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn antialiased_square_wave() {
+        let mut gui = create_test_gui(4);
+        gui.options.antialias = true;
+        gui.options.dither = Dither::None;
+        // half a cycle per row, and the wave is high for half of that
+        gui.draw_single_wave(&square(), Voice { duty: 0.25, ..voice(500.0) });
+        assert_eq!(gui.sink().frames()[1], vec![127, 0, 127, 0]);
+
+        // the edges' gray levels get dithered like any other wave's
+        gui.options.dither = Dither::ErrorDiffusion;
+        gui.draw_single_wave(&square(), Voice { duty: 0.25, ..voice(500.0) });
+        assert_eq!(gui.sink().frames()[2], vec![127, 0, 128, 0]);
+    }
+
+    #[test]
+    fn antialiased_sawtooth_wave() {
+        let mut gui = create_test_gui(4);
+        gui.options.antialias = true;
+        gui.options.dither = Dither::None;
+        // each row averages half a cycle: rising to the middle, then from it
+        gui.draw_single_wave(&(Arc::new(Sawtooth) as Waveform), voice(500.0));
+        assert_eq!(gui.sink().frames()[1], vec![63, 191, 63, 191]);
+    }
+
+    #[test]
+    fn cached_frames_are_reused() {
+        let timing = ScanTiming::from_line_rate(1000.0);
+        let mut gui = Gui::create(vec![(MemorySink::new(64), Some(timing))],
+                                  FrameCache::new(1024),
+                                  RenderOptions::default());
+        // a 250Hz wave has a whole number of cycles in each frame,
+        // so every frame starts at the same phase
+        gui.screens[0].signal = Signal::Tones {
+            waveform: cosine(),
+            voices: vec![voice(250.0)],
+            onsets: vec![0.0],
+        };
+        gui.present_frame(0, 1);
+        gui.present_frame(0, 2);
+        // otherwise, dithering would make these different
+        let frames = gui.sink().frames();
+        assert_eq!(frames[1], frames[2]);
+    }
+
+    #[test]
+    fn replayed_notes_come_from_the_cache() {
+        let timing = ScanTiming::from_line_rate(1000.0);
+        let mut gui = Gui::create(vec![(MemorySink::new(4), Some(timing))],
+                                  FrameCache::new(1024),
+                                  RenderOptions::default());
+        gui.draw_single_wave(&cosine(), voice(101.0));
+        gui.clear_and_present(Color::BLACK);
+        // strike it again 10 frames later, when the note is at another
+        // phase since the GUI was created
+        gui.start -= Duration::from_millis(40);
+        gui.draw_single_wave(&cosine(), voice(101.0));
+        let frames = gui.sink().frames();
+        assert_eq!(frames[1], frames[3]);
+        assert_eq!(gui.stats().cache_lookups, 2);
+        assert_eq!(gui.stats().cache_hits, 1);
+    }
+
+    #[test]
+    fn cached_frames_match_uncached_ones() {
+        // a low note on a 1080p60 panel, where a 1/256 cycle step
+        // would be more than a row
+        let timing = ScanTiming::from_line_rate(67_500.0);
+        let options = RenderOptions { dither: Dither::None, ..RenderOptions::default() };
+        let frame = |cache_size| {
+            let mut gui = Gui::create(vec![(MemorySink::new(1080), Some(timing))],
+                                      FrameCache::new(cache_size),
+                                      options.clone());
+            gui.screens[0].signal = Signal::Tones {
+                waveform: cosine(),
+                voices: vec![voice(110.0)],
+                onsets: vec![0.0],
+            };
+            gui.present_frame(0, 7);
+            gui.sink().frames()[1].clone()
+        };
+        let (cached, uncached) = (frame(64 * 1024 * 1024), frame(0));
+        let max_difference = cached.iter().zip(&uncached)
+            .map(|(cached, uncached)| cached.abs_diff(*uncached))
+            .max()
+            .unwrap();
+        assert!(max_difference <= 1, "rows differ by up to {} gray levels", max_difference);
+
+        // off by at most half a step's worth of time
+        for phase in [0.0, 0.1234, 0.5, 0.9999] {
+            let (_, rounded) = round_phase(phase, 110.0, 67_500.0);
+            let rows_off = (rounded - phase).abs() / 110.0 * 67_500.0;
+            assert!(rows_off <= 0.5 / CACHE_STEPS_PER_ROW + 1e-9, "{} rows off", rows_off);
+        }
+    }
+
+    #[test]
+    fn square_waves_are_averaged() {
+        let mut gui = create_test_gui(4);
+        gui.draw_waves(&square(), &[
+            voice(250.0),
+            voice(500.0),
+        ]);
+        assert_eq!(gui.sink().frames()[1], vec![255, 127, 127, 0]);
+    }
+
+    #[test]
+    fn square_waves_are_weighted() {
+        let mut gui = create_test_gui(4);
+        gui.draw_waves(&square(), &[
+            Voice { amplitude: 3.0, ..voice(250.0) },
+            voice(500.0),
+        ]);
+        // levels are 4, 2, -2 and -4 out of 4
+        assert_eq!(gui.sink().frames()[1], vec![255, 191, 63, 0]);
+    }
+
+    #[test]
+    fn single_cosine_wave_rows() {
+        let mut gui = create_test_gui(4);
+        gui.draw_single_wave(&cosine(), voice(250.0));
+        let expected = [255, 127, 0, 127];
+        for (level, expected) in gui.sink().frames()[1].iter().zip(expected) {
+            // account for dither
+            assert!((*level as i32 - expected).abs() <= 8,
+                    "level = {}, expected = {}", level, expected);
+        }
+    }
+
+    #[test]
+    fn modulation_depth_and_offset() {
+        let mut gui = create_test_gui(4);
+        gui.options.depth = 0.5;
+        gui.draw_single_wave(&square(), voice(250.0));
+        assert_eq!(gui.sink().frames()[1], vec![191, 191, 63, 63]);
+
+        gui.options.offset = 0.25;
+        gui.draw_single_wave(&square(), voice(250.0));
+        assert_eq!(gui.sink().frames()[2], vec![127, 127, 0, 0]);
+    }
+
+    #[test]
+    fn silence_with_depth_and_offset() {
+        let mut gui = create_test_gui(4);
+        gui.options.depth = 0.5;
+        gui.options.offset = 0.25;
+        // no notes at all: the signal sits at the offset
+        gui.draw_waves(&square(), &[]);
+        assert_eq!(gui.sink().frames()[1], vec![63; 4]);
+        // a rest swings no further than the notes do
+        gui.clear_and_present(Color::BLACK);
+        assert_eq!(gui.sink().frames()[2], vec![0; 4]);
+        gui.options.offset = 0.5;
+        gui.clear_and_present(Color::BLACK);
+        assert_eq!(gui.sink().frames()[3], vec![64; 4]);
+    }
+
+    #[test]
+    fn crt_carrier_pattern() {
+        let timing = DisplayTiming::from_short_timing("1,8,10,4,5").unwrap().scan_timing();
+        let options = RenderOptions {
+            crt_carrier: Some(250_000.0),
+            ..RenderOptions::default()
+        };
+        let gui = Gui::create(vec![(MemorySink::new(4), Some(timing))],
+                              FrameCache::new(0),
+                              options);
+        // a frame takes 50 pixels, i.e. 12.5 carrier cycles
+        let frame_period = gui.screens[0].frame_period();
+        assert_eq!(gui.row_pattern(0, frame_period), RowPattern::Carrier(Carrier {
+            cycles_per_pixel: 0.25,
+            cycles_per_row: 2.5,
+            start_phase: 0.5,
+        }));
+    }
+
+    #[test]
+    fn weighted_color_mode() {
+        let mut gui = create_test_gui(4);
+        gui.options.color = ColorMode::parse("1,0.5,0").unwrap();
+        gui.draw_single_wave(&square(), voice(250.0));
+        assert_eq!(gui.sink().color_frames()[1], vec![
+            [255, 127, 0],
+            [255, 127, 0],
+            [0, 0, 0],
+            [0, 0, 0],
+        ]);
+    }
+
+    #[test]
+    fn split_voices_color_mode() {
+        let mut gui = create_test_gui(4);
+        gui.options.color = ColorMode::SplitVoices;
+        gui.draw_waves(&square(), &[voice(250.0), voice(500.0)]);
+        // blue has no note, so it's silent, in the middle
+        assert_eq!(gui.sink().color_frames()[1], vec![
+            [255, 255, 127],
+            [255, 0, 127],
+            [0, 255, 127],
+            [0, 0, 127],
+        ]);
+
+        // silence sits at the offset on every channel, as it does in gray
+        gui.options.depth = 0.5;
+        gui.options.offset = 0.25;
+        gui.draw_waves(&square(), &[]);
+        assert_eq!(gui.sink().color_frames()[2], vec![[63, 63, 63]; 4]);
+    }
+
+    #[test]
+    fn parse_color_mode() {
+        assert_eq!(ColorMode::parse("gray"), Ok(ColorMode::Gray));
+        assert_eq!(ColorMode::parse("green"), Ok(ColorMode::Weighted([0.0, 1.0, 0.0])));
+        assert_eq!(ColorMode::parse("split"), Ok(ColorMode::SplitVoices));
+        assert!(ColorMode::parse("purple").is_err());
+        assert!(ColorMode::parse("1,0").is_err());
+        assert!(ColorMode::parse("1,2,0").is_err());
+    }
+
+    #[test]
+    fn seeded_frames_are_reproducible() {
+        let render = || {
+            let mut gui = create_test_gui(16);
+            gui.options.dither = Dither::Tpdf;
+            gui.rng = StdRng::seed_from_u64(42);
+            gui.draw_single_wave(&cosine(), voice(100.0));
+            gui.sink().frames()[1].clone()
+        };
+        assert_eq!(render(), render());
+    }
+
+    #[test]
+    fn sigma_delta_cosine_wave_is_black_and_white() {
+        let mut gui = create_test_gui(64);
+        gui.options.encoding = Encoding::SigmaDelta { order: 2 };
+        gui.draw_single_wave(&cosine(), voice(125.0));
+        let rows = &gui.sink().frames()[1];
+        assert!(rows.iter().all(|row| *row == 0 || *row == 255));
+        // mostly lit near the wave's peak, mostly dark near its trough
+        let lit = |range: std::ops::Range<usize>| {
+            rows[range].iter().filter(|row| **row == 255).count()
+        };
+        assert!(lit(0..4) >= 3);
+        assert!(lit(2..6) > lit(4..8));
+        assert!(lit(4..8) <= 2);
+    }
+
+    #[test]
+    fn safe_mode_reduces_contrast_and_changes() {
+        let options = RenderOptions { safe_mode: true, ..RenderOptions::default() };
+        let timing = ScanTiming::from_line_rate(1000.0);
+        let mut gui = Gui::create(vec![(MemorySink::new(4), Some(timing))],
+                                  FrameCache::new(0), options);
+        gui.draw_single_wave(&square(), voice(250.0));
+        assert_eq!(gui.sink().frames()[1], vec![159, 159, 95, 95]);
+        // too soon after the previous change, so it's held back
+        gui.clear_and_present(Color::BLACK);
+        gui.refresh();
+        assert!(gui.sink().frames()[1..].iter().all(|frame| *frame == vec![159, 159, 95, 95]));
+        assert_eq!(gui.reduce_contrast(Color::BLACK), Color::RGB(96, 96, 96));
+    }
+
+    #[test]
+    fn frames_presented_before_they_start_arent_missed() {
+        // 1 second per frame, so the next one starts long after it's presented
+        let timing = ScanTiming::from_line_rate(4.0);
+        let mut gui = Gui::create(vec![(MemorySink::new(4), Some(timing))],
+                                  FrameCache::new(0),
+                                  RenderOptions::default());
+        let due = Instant::now();
+        gui.draw_single_wave(&square(), voice(1.0));
+        gui.record_note_change(due);
+        assert_eq!(gui.stats().missed_deadlines, 0);
+        assert_eq!(gui.stats().note_latency.count(), 1);
+        assert!(gui.stats().note_latency.percentile(1.0) < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn safe_mode_keeps_every_encoding_within_depth() {
+        // offset ± SAFE_DEPTH / 2, in gray levels
+        let (lowest, highest) = ((255.0 * 0.375) as u8, (255.0 * 0.625f64).round() as u8);
+        for encoding in [Encoding::Gray,
+                         Encoding::SigmaDelta { order: 1 },
+                         Encoding::SigmaDelta { order: 2 }] {
+            let options = RenderOptions {
+                encoding,
+                dither: Dither::None,
+                safe_mode: true,
+                ..RenderOptions::default()
+            };
+            let timing = ScanTiming::from_line_rate(1000.0);
+            let mut gui = Gui::create(vec![(MemorySink::new(64), Some(timing))],
+                                      FrameCache::new(0), options);
+            gui.draw_waves(&cosine(), &[voice(30.0), voice(70.0)]);
+            let frame = gui.sink().frames().last().unwrap().clone();
+            assert!(frame.iter().all(|level| (lowest..=highest).contains(level)),
+                    "{:?}: {:?}", encoding, frame);
+        }
+    }
+
+    #[test]
+    fn screens_have_their_own_timing_and_signal() {
+        let screens = vec![(MemorySink::new(4), Some(ScanTiming::from_line_rate(1000.0))),
+                           (MemorySink::new(4), Some(ScanTiming::from_line_rate(500.0)))];
+        let mut gui = Gui::create(screens, FrameCache::new(1024), RenderOptions::default());
+        gui.draw_single_wave(&square(), voice(250.0));
+        assert_eq!(gui.screens[0].sink.frames().last().unwrap(), &vec![255, 255, 0, 0]);
+        assert_eq!(gui.screens[1].sink.frames().last().unwrap(), &vec![255, 0, 255, 0]);
+
+        let first_screen_frames = gui.screens[0].sink.frames().len();
+        gui.draw_waves_on(1, &square(), &[voice(125.0)]);
+        assert_eq!(gui.screens[0].sink.frames().len(), first_screen_frames);
+        assert_eq!(gui.screens[1].sink.frames().last().unwrap(), &vec![255, 255, 0, 0]);
+    }
+
+    fn chord(voices: usize) -> (Vec<Voice>, Vec<f64>) {
+        let voices: Vec<Voice> = (0..voices)
+            .map(|index| voice(440.0 * 1.06f64.powi(index as i32)))
+            .collect();
+        let start_phases = (0..voices.len()).map(|index| 0.1 * index as f64).collect();
+        (voices, start_phases)
+    }
+
+    #[test]
+    fn parallel_rows_match_serial_rows() {
+        let (voices, start_phases) = chord(16);
+        let pool = WorkerPool::new(7);
+        for waveform in [square(), cosine()] {
+            let mut serial = vec![0.0; 2160];
+            let mut parallel = vec![0.0; 2160];
+            render_tones(waveform.as_ref(), &voices, &start_phases, 135_000.0, true,
+                         0, &mut serial);
+            // twice, with the same threads
+            for _ in 0..2 {
+                render_tones_parallel(&pool, &waveform, &voices, &start_phases, 135_000.0,
+                                      true, &mut parallel);
+                assert_eq!(serial, parallel);
+            }
+        }
+    }
+
+    // The cosine computed with `cos`, as it was before using a table.
+    struct ExactCosine;
+
+    impl WaveShape for ExactCosine {
+        fn name(&self) -> String {
+            "exact cosine".to_string()
+        }
+
+        fn value(&self, phase: f64, _duty: f64) -> f64 {
+            (std::f64::consts::TAU * phase).cos()
+        }
+    }
+
+    // Levels computed the way they were before phases were computed from
+    // per-voice increments, one row at a time.
+    fn render_tones_by_time(waveform: &dyn WaveShape,
+                            voices: &[Voice],
+                            start_phases: &[f64],
+                            line_rate: f64,
+                            levels: &mut [f64]) {
+        let total_ampl: f64 = voices.iter().map(|voice| voice.amplitude).sum();
+        for (y, row_level) in levels.iter_mut().enumerate() {
+            let t = (y as f64) / line_rate;
+            let mut level = 0.0;
+            for (voice, start_phase) in voices.iter().zip(start_phases) {
+                level += voice.amplitude * waveform.value(start_phase + t * voice.freq, voice.duty);
+            }
+            *row_level = (level + total_ampl) / total_ampl / 2.0;
+        }
+    }
+
+    #[test]
+    fn phase_increments_keep_gray_levels() {
+        let (voices, start_phases) = chord(16);
+        let sawtooth = Arc::new(Sawtooth) as Waveform;
+        let cases = [
+            (square(), square()),
+            (cosine(), Arc::new(ExactCosine) as Waveform),
+            (sawtooth.clone(), sawtooth),
+        ];
+        for (waveform, before) in cases {
+            let mut by_increment = vec![0.0; 2160];
+            let mut by_time = vec![0.0; 2160];
+            render_tones(waveform.as_ref(), &voices, &start_phases, 135_000.0, false,
+                         0, &mut by_increment);
+            render_tones_by_time(before.as_ref(), &voices, &start_phases, 135_000.0,
+                                 &mut by_time);
+            for (y, (new, old)) in by_increment.iter().zip(&by_time).enumerate() {
+                assert!((new - old).abs() < 1e-7, "row {}: {} vs {}", y, new, old);
+                // as `encode_gray` turns them into gray levels
+                assert_eq!((new * 255.0) as u8, (old * 255.0) as u8, "row {}", y);
+            }
+        }
+    }
+
+    // Run with `cargo test --release render_speed -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn render_speed() {
+        const FRAMES: u32 = 50;
+        const ROUNDS: usize = 10;
+        // a 4K panel at 60Hz, playing a dense chord
+        let (voices, start_phases) = chord(16);
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let mut levels = vec![0.0; 2160];
+        // the best of several rounds, which is the least disturbed by other processes
+        let mut frame_time = |render: &mut dyn FnMut(&mut [f64])| {
+            (0..ROUNDS).map(|_| {
+                let start = Instant::now();
+                for _ in 0..FRAMES {
+                    render(&mut levels);
+                }
+                start.elapsed() / FRAMES
+            }).min().unwrap()
+        };
+        let before = frame_time(&mut |levels| {
+            render_tones_by_time(&ExactCosine, &voices, &start_phases, 135_000.0, levels);
+        });
+        println!("cos, by time (1 thread): {:?} per frame", before);
+        let cosine = cosine();
+        let mut thread_counts = vec![1];
+        if threads > 1 {
+            thread_counts.push(threads);
+        }
+        for threads in thread_counts {
+            let pool = WorkerPool::new(threads);
+            let after = frame_time(&mut |levels| {
+                render_tones_parallel(&pool, &cosine, &voices, &start_phases, 135_000.0,
+                                      false, levels);
+            });
+            println!("table, by increment ({} threads): {:?} per frame, {:.2}x as fast",
+                     threads, after, before.as_secs_f64() / after.as_secs_f64());
+        }
+    }
 }
@@ -10,6 +10,7 @@ use std::time::Duration;
 pub struct Note {
     pub freq: Option<f64>, // in Hz; None means the note is a rest
     pub duration: Duration,
+    pub duty: Option<f64>, // between 0 and 1; None means the default one
 }
 
 pub fn parse_file_contents(file_contents: &str) -> Vec<Note> {
@@ -27,7 +28,7 @@ pub fn parse_file_contents(file_contents: &str) -> Vec<Note> {
 }
 
 fn parse_note(bpm: f64, note: &str) -> Note {
-    let mut chars = note.chars();
+    let mut chars = note.chars().peekable();
     // we've filtered for word.is_empty() so this should have at least one char
     let note_name = chars.next().unwrap();
     let semitone_offset = semitone_offset_from_la(note_name);
@@ -39,7 +40,7 @@ fn parse_note(bpm: f64, note: &str) -> Note {
         let octave_char = chars.next()
             .expect("missing octave number in note!");
         let note_octave = octave_char.to_digit(10)
-            .expect(&format!("note octave should be a digit: got {}", octave_char));
+            .unwrap_or_else(|| panic!("note octave should be a digit: got {}", octave_char));
 
         // counted from A0 = 0. This is NOT the same as the MIDI number.
         let key_number = ((note_octave as i32) * 12 + semitone as i32) as f64; 
@@ -48,14 +49,25 @@ fn parse_note(bpm: f64, note: &str) -> Note {
     } else {
         None
     };
-    let duration_factor = match chars.next() {
+    let duration_factor = match chars.next_if(|c| *c != '%') {
         Some(c) => duration_factor_from_quarter(c),
         None => 1.0, // notes are quarters by default
     };
+    let duty = chars.next_if_eq(&'%').map(|_| {
+        let percent: String = chars.by_ref().collect();
+        let duty = percent.parse::<f64>()
+            .unwrap_or_else(|e| panic!("failed to parse duty cycle `{}': {}", percent, e))
+            / 100.0;
+        if duty <= 0.0 || duty > 1.0 {
+            panic!("duty cycle must be above 0% and at most 100%: got `{}'", percent);
+        }
+        duty
+    });
     let duration_ms = (duration_factor * 60_000.0 / bpm).round();
     Note {
         freq,
         duration: Duration::from_millis(duration_ms as u64),
+        duty,
     }
 }
 
@@ -131,6 +143,21 @@ mod tests {
         assert_eq!(note.freq, None);
         assert_eq!(note.duration, expected_duration);
     }
+    #[test]
+    fn parse_duty() {
+        let note = parse_note(120.0, "a4e%25");
+        test_note(&note, 440.0, Duration::from_millis(250));
+        assert_eq!(note.duty, Some(0.25));
+
+        // without duration
+        let note = parse_note(120.0, "a4%12.5");
+        test_note(&note, 440.0, Duration::from_millis(500));
+        assert_eq!(note.duty, Some(0.125));
+
+        let note = parse_note(120.0, "a4");
+        assert_eq!(note.duty, None);
+    }
+
     #[test]
     fn test_parse_contents1() {
         let contents = "150\na2 c#3 e3";
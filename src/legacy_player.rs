@@ -6,28 +6,36 @@ use sdl2::pixels::Color;
 
 use crate::{
     legacy_parser::Note,
-    gui::Gui,
+    frame_sink::FrameSink,
+    gui::{Gui, Voice},
+    waveform::Waveform,
 };
 
 const SLEEP_INTERVAL: Duration = Duration::from_millis(5);
 
-pub struct LegacyPlayer {
-    gui: Gui,
+pub struct LegacyPlayer<S: FrameSink> {
+    gui: Gui<S>,
     running: bool,
     paused: bool,
-    wave_is_cosine: bool,
+    waveform: Waveform,
+    default_duty: f64,
 }
 
-impl LegacyPlayer {
-    pub fn create(gui: Gui, wave_is_cosine: bool) -> Self {
+impl<S: FrameSink> LegacyPlayer<S> {
+    pub fn create(gui: Gui<S>, waveform: Waveform, default_duty: f64) -> Self {
         LegacyPlayer {
             gui,
             paused: false,
             running: false,
-            wave_is_cosine,
+            waveform,
+            default_duty,
         }
     }
 
+    pub fn into_gui(self) -> Gui<S> {
+        self.gui
+    }
+
     // this is only necessary because we can't pass mutable references
     // of our fields to Gui directly
     pub fn handle_events(&mut self) {
@@ -40,8 +48,8 @@ impl LegacyPlayer {
         self.paused = paused;
     }
 
-    pub fn run(&mut self, notes: &Vec<Note>) {
-        if self.running || notes.len() == 0 {
+    pub fn run(&mut self, notes: &[Note]) {
+        if self.running || notes.is_empty() {
             return;
         }
 
@@ -49,14 +57,17 @@ impl LegacyPlayer {
         let mut cur_index = 0;
         let mut time_playing_cur_note = Duration::ZERO;
         let mut iteration_start;
-        let mut previously_paused = false;
+        // when the current note was due, had we not been paused
+        let mut schedule_start = Instant::now();
+        let mut cur_note_due = Duration::ZERO;
+        let mut paused_since = None;
 
         // Special care must be taken to ensure first note is actually played.
         // We must handle events before playing it as well, because there's
-        // a good chance we'll receive some event (like Shown or FocusGained)
+        // a good chance we'll receive some event (like FocusLost)
         // that would cause the screen to go blank.
         self.handle_events();
-        self.play_note(&notes[0]);
+        self.play_note(&notes[0], Some(schedule_start));
 
         'main_loop: loop {
             iteration_start = Instant::now();
@@ -67,39 +78,85 @@ impl LegacyPlayer {
             }
             if self.paused {
                 thread::sleep(SLEEP_INTERVAL);
-                previously_paused = true;
+                paused_since.get_or_insert_with(Instant::now);
                 continue;
-            } else if previously_paused {
-                self.play_note(cur_note);
-                previously_paused = false;
+            } else if let Some(pause_start) = paused_since.take() {
+                schedule_start += pause_start.elapsed();
+                self.play_note(cur_note, None);
             }
 
             if time_playing_cur_note > cur_note.duration {
                 time_playing_cur_note = Duration::ZERO;
+                cur_note_due += cur_note.duration;
                 cur_index += 1;
                 if cur_index >= notes.len() {
                     break 'main_loop;
                 }
                 let new_note = &notes[cur_index];
-                self.play_note(new_note);
+                self.play_note(new_note, Some(schedule_start + cur_note_due));
             }
+            self.gui.refresh();
             thread::sleep(SLEEP_INTERVAL);
             time_playing_cur_note += iteration_start.elapsed();
         }
         self.running = false;
     }
 
-    fn play_note(&mut self, new_note: &Note) {
+    // `due` is when the note should have started, if it's a note change.
+    fn play_note(&mut self, new_note: &Note, due: Option<Instant>) {
         match new_note.freq {
             // note
             Some(freq) => {
-                if self.wave_is_cosine {
-                    self.gui.draw_single_cosine_wave(freq);
-                } else {
-                    self.gui.draw_single_square_wave(freq);
-                }
+                let voice = Voice {
+                    freq,
+                    duty: new_note.duty.unwrap_or(self.default_duty),
+                    amplitude: 1.0,
+                };
+                self.gui.draw_single_wave(&self.waveform, voice);
             },
             None => self.gui.clear_and_present(Color::BLACK), // rest
         };
+        if let Some(due) = due {
+            self.gui.record_note_change(due);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        frame_cache::FrameCache,
+        frame_sink::MemorySink,
+        gui::RenderOptions,
+        timing::ScanTiming,
+        waveform::Square,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn plays_notes_in_order() {
+        let timing = ScanTiming::from_line_rate(1000.0);
+        let gui = Gui::create(vec![(MemorySink::new(4), Some(timing))],
+                              FrameCache::new(0),
+                              RenderOptions::default());
+        let mut player = LegacyPlayer::create(gui, Arc::new(Square), 0.5);
+        let notes = [
+            Note { freq: Some(250.0), duration: Duration::from_millis(10), duty: None },
+            Note { freq: None, duration: Duration::from_millis(10), duty: None },
+        ];
+        player.run(&notes);
+
+        // held notes are rendered once per frame, but since each frame
+        // holds exactly one period of the note, they should all look the same
+        let gui = player.into_gui();
+        let mut frames = gui.sink().frames().to_vec();
+        frames.dedup();
+        assert_eq!(frames, &[
+            vec![128, 128, 128, 128], // startup
+            vec![255, 255, 0, 0],
+            vec![0, 0, 0, 0],
+        ]);
+        assert_eq!(gui.stats().note_latency.count(), 2);
     }
 }
@@ -6,19 +6,51 @@
 // See COPYING.txt.
 
 mod args;
+mod calibration;
+mod edid;
+mod encoding;
+mod frame_cache;
+mod frame_sink;
+mod frame_stats;
 mod gui;
 mod legacy_parser;
 mod legacy_player;
 mod midi_player;
+mod timing;
+mod waveform;
+mod worker_pool;
 
-use std::fs;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Arc,
+};
 use clap::Parser;
 use midly::Smf;
+use sdl2::{
+    messagebox::{self, ButtonData, ClickedButton, MessageBoxButtonFlag, MessageBoxFlag},
+    pixels::Color,
+    video::Window,
+};
 
-use gui::Gui;
+use gui::{Gui, RenderOptions};
 use args::Args;
+use calibration::Calibration;
+use encoding::Encoding;
+use frame_cache::FrameCache;
+use frame_sink::{FrameSink, MemorySink, SdlSink};
 use legacy_player::LegacyPlayer;
 use midi_player::MidiPlayer;
+use timing::{DisplayTiming, ScanTiming};
+use waveform::{Cosine, Waveform};
+
+const PHOTOSENSITIVITY_WARNING: &str = "\
+WARNING: while playing, the whole screen flashes high-contrast patterns
+that change with every note, which may trigger seizures in people with
+photosensitive epilepsy. Make sure nobody who could be affected can see
+the screen, or use --safe-mode to reduce the contrast and how often the
+screen changes.
+Press any key in the (black) window to continue, or Q to quit.";
 
 fn main() {
     let arg_data = Args::parse();
@@ -26,26 +58,194 @@ fn main() {
     let file_contents = fs::read(filename)
         .unwrap_or_else(|e| panic!("failed to read file {}: {}", filename, e));
 
-    let gui = Gui::create(arg_data.horiz_refresh_rate);
+    match arg_data.headless {
+        Some(rows) => {
+            let gui = create_gui(vec![MemorySink::counting(rows)], &arg_data);
+            let gui = play(gui, &arg_data, file_contents);
+            println!("rendered {} frames", gui.sink().frame_count());
+            if arg_data.stats {
+                print!("{}", gui.stats().summary());
+            }
+        },
+        None => {
+            let displays: Vec<Option<&str>> = if arg_data.display.is_empty() {
+                vec![None]
+            } else {
+                arg_data.display.iter().map(|display| Some(display.as_str())).collect()
+            };
+            // shown before any window covers the screen
+            let confirmed = if arg_data.skip_warning { Some(true) } else { confirm_warning() };
+            if confirmed == Some(false) {
+                return;
+            }
+            let mut sinks = SdlSink::create_all(&displays, arg_data.orientation);
+            if confirmed.is_none() {
+                eprintln!("Press any key in the (black) window to continue, or Q to quit.");
+                for sink in sinks.iter_mut() {
+                    sink.clear_and_present(Color::BLACK);
+                }
+                if !sinks[0].wait_for_keypress() {
+                    return;
+                }
+            }
+            let gui = create_gui(sinks, &arg_data);
+            let gui = play(gui, &arg_data, file_contents);
+            if arg_data.stats {
+                print!("{}", gui.stats().summary());
+            }
+        },
+    }
+}
+
+// Show the photosensitivity warning, and return whether to go on playing,
+// or `None` if it couldn't be shown in a message box (only on stderr).
+fn confirm_warning() -> Option<bool> {
+    eprintln!("{}", PHOTOSENSITIVITY_WARNING);
+    const CONTINUE: i32 = 1;
+    let buttons = [
+        ButtonData {
+            flags: MessageBoxButtonFlag::ESCAPEKEY_DEFAULT,
+            button_id: 0,
+            text: "Quit",
+        },
+        ButtonData {
+            flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT,
+            button_id: CONTINUE,
+            text: "Continue",
+        },
+    ];
+    let clicked = messagebox::show_message_box(MessageBoxFlag::WARNING,
+                                               &buttons,
+                                               "tempest-lcd",
+                                               PHOTOSENSITIVITY_WARNING,
+                                               None::<&Window>,
+                                               None);
+    match clicked {
+        Ok(ClickedButton::CustomButton(button)) => Some(button.button_id == CONTINUE),
+        Ok(ClickedButton::CloseButton) => Some(false),
+        Err(e) => {
+            eprintln!("failed to show the warning in a message box: {}", e);
+            None
+        },
+    }
+}
 
+fn create_gui<S: FrameSink>(sinks: Vec<S>, arg_data: &Args) -> Gui<S> {
+    let timing_counts = [arg_data.horiz_refresh_rate.len(),
+                         arg_data.modeline.len(),
+                         arg_data.timing.len()];
+    if timing_counts.iter().any(|count| *count != 0 && *count != sinks.len()) {
+        panic!("the display timing must be given once for each of the {} displays",
+               sinks.len());
+    }
+    // there's no telling which display each EDID in /sys/class/drm is for
+    if sinks.len() > 1 && arg_data.edid.is_some() {
+        panic!("--edid can only be used with a single display");
+    }
+    let sinks = sinks.into_iter()
+        .enumerate()
+        .map(|(index, sink)| {
+            let timing = screen_timing(&sink, index, arg_data);
+            (sink, timing)
+        })
+        .collect();
+    let frame_cache = FrameCache::new(arg_data.frame_cache_mb * 1024 * 1024);
+    let options = RenderOptions {
+        antialias: arg_data.antialias,
+        encoding: match (arg_data.sigma_delta, arg_data.lit_width) {
+            (Some(order), _) => Encoding::SigmaDelta { order },
+            (None, Some(layout)) => Encoding::Width(layout),
+            (None, None) => Encoding::Gray,
+        },
+        dither: arg_data.dither,
+        seed: arg_data.seed,
+        calibration: arg_data.calibration.as_ref().map(|path| {
+            Calibration::load(path).unwrap_or_else(|e| panic!("{}", e))
+        }),
+        color: arg_data.color,
+        depth: arg_data.depth / 100.0,
+        offset: arg_data.offset / 100.0,
+        crt_carrier: arg_data.crt_carrier.map(|carrier_mhz| carrier_mhz * 1e6),
+        max_change_rate: arg_data.max_change_rate,
+        safe_mode: arg_data.safe_mode,
+    };
+    if options.offset - options.depth / 2.0 < 0.0 || options.offset + options.depth / 2.0 > 1.0 {
+        eprintln!("warning: with depth {}% and offset {}%, the signal will be clipped",
+                  arg_data.depth, arg_data.offset);
+    }
+    Gui::create(sinks, frame_cache, options)
+}
+
+// The timing given for the display at the given index, if any.
+fn screen_timing<S: FrameSink>(sink: &S, index: usize, arg_data: &Args) -> Option<ScanTiming> {
+    let display_timing = match &arg_data.edid {
+        Some(edid_path) => Some(edid_timing(edid_path.as_ref(), sink.rows(), sink.refresh_rate())),
+        None => arg_data.modeline.get(index).or(arg_data.timing.get(index)).copied(),
+    };
+    match display_timing {
+        Some(display_timing) => {
+            if display_timing.vdisplay as usize != sink.rows() {
+                eprintln!("warning: display timing has {} visible rows, but the screen has {}",
+                          display_timing.vdisplay,
+                          sink.rows());
+            }
+            Some(display_timing.scan_timing())
+        },
+        // if this is also missing, let Gui detect it
+        None => arg_data.horiz_refresh_rate.get(index).copied().map(ScanTiming::from_line_rate),
+    }
+}
+
+fn edid_timing(edid_path: Option<&PathBuf>, rows: usize, refresh_rate: Option<f64>)
+    -> DisplayTiming {
+    let paths = match edid_path {
+        Some(path) => vec![path.clone()],
+        None => edid::find_connected_edids(),
+    };
+    if paths.is_empty() {
+        panic!("no connected monitor found: please give the path to an EDID file");
+    }
+    let (path, timing) = edid::choose_timing(&paths, rows, refresh_rate)
+        .unwrap_or_else(|e| panic!("failed to get timing from EDID: {}", e));
+    println!("using {}x{} timing at {:.2}Hz from {}: {} rows per second",
+             timing.hdisplay,
+             timing.vdisplay,
+             timing.refresh_rate(),
+             path.display(),
+             timing.line_rate());
+    timing
+}
+
+fn play<S: FrameSink>(gui: Gui<S>, arg_data: &Args, file_contents: Vec<u8>) -> Gui<S> {
+    let filename = &arg_data.filename;
+    let waveform: Waveform = if arg_data.cosine {
+        Arc::new(Cosine)
+    } else {
+        arg_data.waveform.clone()
+    };
     if arg_data.midi {
         let mut player = MidiPlayer::create(gui,
-                                            arg_data.cosine,
-                                            arg_data.channel);
+                                            waveform,
+                                            &arg_data.channel,
+                                            arg_data.duty / 100.0);
         let smf = Smf::parse(&file_contents)
             .unwrap_or_else(|e| panic!(
                         "failed to parse MIDI file '{}': '{}'",
                         filename,
                         e));
         player.run(smf);
+        player.into_gui()
     } else {
         let file_contents_str = String::from_utf8(file_contents)
             .unwrap_or_else(|e| panic!(
                     "failed to convert file '{}' contents to string: '{}'",
                     filename,
                     e));
-        let mut player = LegacyPlayer::create(gui, arg_data.cosine);
+        let mut player = LegacyPlayer::create(gui,
+                                              waveform,
+                                              arg_data.duty / 100.0);
         let notes = legacy_parser::parse_file_contents(&file_contents_str);
         player.run(&notes);
+        player.into_gui()
     }
 }
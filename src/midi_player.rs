@@ -6,7 +6,7 @@
 // See COPYING.txt.
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, btree_map::Entry},
     thread,
     time::{Duration, Instant},
 };
@@ -24,7 +24,9 @@ use midly::{
 };
 
 use crate::{
-    gui::Gui,
+    frame_sink::FrameSink,
+    gui::{Gui, Voice},
+    waveform::Waveform,
 };
 
 // equals 120BPM if quarter is the beat
@@ -32,25 +34,44 @@ const DEFAULT_MICROSEC_PER_QUATER : u32 = 500_000;
 
 const PAUSE_SLEEP_INTERVAL: Duration = Duration::from_millis(5);
 
-pub struct MidiPlayer {
-    gui: Gui,
+pub struct MidiPlayer<S: FrameSink> {
+    gui: Gui<S>,
     running: bool,
     paused: bool,
-    wave_is_cosine: bool,
-    subscribed_channel: u8,
+    waveform: Waveform,
+    // which channel each screen plays
+    subscribed_channels: Vec<u8>,
+    duty: f64,
 }
 
-impl MidiPlayer {
-    pub fn create(gui: Gui, wave_is_cosine: bool, subscribed_channel: u8) -> Self {
+impl<S: FrameSink> MidiPlayer<S> {
+    /// `subscribed_channels` has the channel to play on each of the GUI's
+    /// screens, or a single one to play on all of them.
+    pub fn create(gui: Gui<S>,
+                  waveform: Waveform,
+                  subscribed_channels: &[u8],
+                  duty: f64) -> Self {
+        let subscribed_channels = match subscribed_channels {
+            [channel] => vec![*channel; gui.screen_count()],
+            _ if subscribed_channels.len() == gui.screen_count() => subscribed_channels.to_vec(),
+            _ => panic!("{} MIDI channels given for {} screens: give one per screen, \
+                         or a single one for all of them",
+                        subscribed_channels.len(), gui.screen_count()),
+        };
         MidiPlayer {
             gui,
             paused: false,
             running: false,
-            wave_is_cosine,
-            subscribed_channel,
+            waveform,
+            subscribed_channels,
+            duty,
         }
     }
 
+    pub fn into_gui(self) -> Gui<S> {
+        self.gui
+    }
+
     // this is only necessary because we can't pass mutable references
     // of our fields to Gui directly (and it's identical to LegacyPlayer)
     pub fn handle_gui_events(&mut self) {
@@ -99,11 +120,18 @@ impl MidiPlayer {
         self.handle_gui_events();
 
         let mut iteration_start;
-        let mut notes_currently_on = HashMap::new();
+        // when events were due, had we not been paused
+        let mut schedule_start = Instant::now();
+        // time into the song of the last time events were handled
+        let mut song_time = Duration::ZERO;
+        let mut paused_since = None;
+        // notes played on each screen, by note number, so that they're always
+        // given to Gui in the same order (which decides their color channel
+        // with `--color split`)
+        let mut notes_currently_on = vec![BTreeMap::new(); self.subscribed_channels.len()];
         let mut sleep_drift;
         let mut sleep_duration = tick_duration;
         let mut ticks_elapsed : u28 = 0.into();
-        let mut previously_paused = false;
         let mut tracks_ended;
 
         'main_loop: loop {
@@ -114,11 +142,13 @@ impl MidiPlayer {
             }
             if self.paused {
                 thread::sleep(PAUSE_SLEEP_INTERVAL);
-                previously_paused = true;
+                paused_since.get_or_insert_with(Instant::now);
                 continue;
-            } else if previously_paused {
-                self.play_notes(&notes_currently_on);
-                previously_paused = false;
+            } else if let Some(pause_start) = paused_since.take() {
+                schedule_start += pause_start.elapsed();
+                for (screen, notes) in notes_currently_on.iter().enumerate() {
+                    self.play_notes(screen, notes, None);
+                }
             }
 
             tracks_ended = true;
@@ -126,8 +156,10 @@ impl MidiPlayer {
                 if let Some(ref mut ev) = upcoming_midi_ev[i] {
                     tracks_ended = false;
                     if ev.delta <= ticks_elapsed {
+                        let due = schedule_start + song_time
+                            + tick_duration * ev.delta.as_int();
                         self.handle_midi_event(ev.kind,
-                                               self.subscribed_channel,
+                                               due,
                                                &mut notes_currently_on,
                                                &mut tick_duration,
                                                timing);
@@ -140,7 +172,9 @@ impl MidiPlayer {
             if tracks_ended {
                 break 'main_loop;
             }
+            song_time += tick_duration * ticks_elapsed.as_int();
 
+            self.gui.refresh();
             thread::sleep(sleep_duration);
 
             sleep_drift = iteration_start.elapsed();
@@ -159,31 +193,38 @@ impl MidiPlayer {
         }
     }
 
+    // `due` is when the event should have been handled.
     fn handle_midi_event<'a>(&mut self,
                              ev_kind: TrackEventKind<'a>,
-                             subscribed_channel: u8,
-                             notes_currently_on: &mut HashMap<u7, u7>,
+                             due: Instant,
+                             notes_currently_on: &mut [BTreeMap<u7, u7>],
                              tick_duration: &mut Duration,
                              midi_timing: Timing) {
         match ev_kind {
-            TrackEventKind::Midi { channel, message }
-            if channel == subscribed_channel => match message {
-                MidiMessage::NoteOn { key, vel } => {
-                    if vel == 0 {
-                        // if velocity was set to zero, remove note instead
-                        // FIXME fade-out note before removing
-                        notes_currently_on.remove(&key);
-                    } else if !notes_currently_on.contains_key(&key) {
-                        notes_currently_on.insert(key, vel);
-                        self.play_notes(notes_currently_on);
+            TrackEventKind::Midi { channel, message } => {
+                for (screen, notes) in notes_currently_on.iter_mut().enumerate() {
+                    if channel != self.subscribed_channels[screen] {
+                        continue;
                     }
-                },
-                MidiMessage::NoteOff { key, vel:_ } => {
-                    // FIXME fade-out note before removing
-                    notes_currently_on.remove(&key);
-                    self.play_notes(notes_currently_on);
-                },
-                _ => {},
+                    match message {
+                        MidiMessage::NoteOn { key, vel } => {
+                            if vel == 0 {
+                                // if velocity was set to zero, remove note instead
+                                // FIXME fade-out note before removing
+                                notes.remove(&key);
+                            } else if let Entry::Vacant(entry) = notes.entry(key) {
+                                entry.insert(vel);
+                                self.play_notes(screen, notes, Some(due));
+                            }
+                        },
+                        MidiMessage::NoteOff { key, vel:_ } => {
+                            // FIXME fade-out note before removing
+                            notes.remove(&key);
+                            self.play_notes(screen, notes, Some(due));
+                        },
+                        _ => {},
+                    }
+                }
             },
             TrackEventKind::Meta(MetaMessage::Tempo(microsec_per_quarter)) => {
                 *tick_duration = get_tick_duration(
@@ -194,15 +235,19 @@ impl MidiPlayer {
         }
     }
 
-    fn play_notes(&mut self, notes_midi: &HashMap<u7, u7>) {
-        // TODO: take velocity into account
-        let notes : Vec<f64> = notes_midi.keys()
-            .map(|num| midi_number_to_freq(*num))
+    // `due` is when the notes should have started, if they're a note change.
+    fn play_notes(&mut self, screen: usize, notes_midi: &BTreeMap<u7, u7>, due: Option<Instant>) {
+        let notes : Vec<Voice> = notes_midi.iter()
+            .map(|(num, vel)| Voice {
+                freq: midi_number_to_freq(*num),
+                duty: self.duty,
+                // notes with zero velocity are never added to the map
+                amplitude: (vel.as_int() as f64) / 127.0,
+            })
             .collect();
-        if self.wave_is_cosine {
-            self.gui.draw_cosine_waves(&notes);
-        } else {
-            self.gui.draw_square_waves(&notes);
+        self.gui.draw_waves_on(screen, &self.waveform, &notes);
+        if let Some(due) = due {
+            self.gui.record_note_change_on(screen, due);
         }
     }
 }
@@ -364,3 +409,59 @@ fn midi_number_to_freq(num: u7) -> f64 {
         invalid => panic!("error: invalid value for u7: {}", invalid),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::{
+        frame_cache::FrameCache,
+        frame_sink::MemorySink,
+        gui::{ColorMode, RenderOptions},
+        timing::ScanTiming,
+        waveform::Square,
+    };
+
+    fn create_test_player() -> MidiPlayer<MemorySink> {
+        let options = RenderOptions { color: ColorMode::SplitVoices, ..RenderOptions::default() };
+        let gui = Gui::create(vec![(MemorySink::new(4), Some(ScanTiming::from_line_rate(1000.0)))],
+                              FrameCache::new(0),
+                              options);
+        MidiPlayer::create(gui, Arc::new(Square), &[0], 0.5)
+    }
+
+    fn send(player: &mut MidiPlayer<MemorySink>,
+            notes: &mut [BTreeMap<u7, u7>],
+            message: MidiMessage) {
+        let mut tick_duration = Duration::from_millis(1);
+        player.handle_midi_event(TrackEventKind::Midi { channel: 0.into(), message },
+                                 Instant::now(),
+                                 notes,
+                                 &mut tick_duration,
+                                 Timing::Metrical(480.into()));
+    }
+
+    fn note_on(key: u8) -> MidiMessage {
+        MidiMessage::NoteOn { key: key.into(), vel: 100.into() }
+    }
+
+    #[test]
+    fn voices_keep_their_order() {
+        let mut player = create_test_player();
+        let mut notes = vec![BTreeMap::new()];
+        for key in [72, 60, 67] {
+            send(&mut player, &mut notes, note_on(key));
+        }
+        let freqs = |player: &MidiPlayer<MemorySink>| -> Vec<f64> {
+            player.gui.voices_on(0).iter().map(|voice| voice.freq).collect()
+        };
+        // lowest note on red, then green, then blue
+        assert_eq!(freqs(&player), vec![261.6256, 391.9954, 523.2511]);
+
+        // the remaining notes keep their relative order
+        send(&mut player, &mut notes, MidiMessage::NoteOff { key: 60.into(), vel: 0.into() });
+        assert_eq!(freqs(&player), vec![391.9954, 523.2511]);
+        send(&mut player, &mut notes, note_on(64));
+        assert_eq!(freqs(&player), vec![329.6276, 391.9954, 523.2511]);
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+/// How quickly the monitor scans rows, which is all `Gui` needs to know
+/// in order to figure out at what time each row is displayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanTiming {
+    /// Rows scanned per second (aka horizontal refresh rate).
+    pub line_rate: f64,
+    /// Rows scanned per frame, including the ones in the vertical blanking
+    /// interval, if known. Otherwise, it's assumed there's no blanking at all.
+    pub total_lines: Option<u32>,
+    /// Pixels scanned per second, if known.
+    /// Only needed to draw a carrier within each row.
+    pub pixel_clock: Option<f64>,
+}
+
+impl ScanTiming {
+    pub fn from_line_rate(line_rate: f64) -> Self {
+        ScanTiming {
+            line_rate,
+            total_lines: None,
+            pixel_clock: None,
+        }
+    }
+}
+
+/// The full timing of a display mode, as found in an X11 modeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayTiming {
+    /// Pixel clock, in Hz.
+    pub pixel_clock: f64,
+    pub hdisplay: u32,
+    pub htotal: u32,
+    pub vdisplay: u32,
+    pub vtotal: u32,
+}
+
+impl DisplayTiming {
+    pub fn line_rate(&self) -> f64 {
+        self.pixel_clock / (self.htotal as f64)
+    }
+
+    /// Frames per second.
+    pub fn refresh_rate(&self) -> f64 {
+        self.line_rate() / (self.vtotal as f64)
+    }
+
+    pub fn scan_timing(&self) -> ScanTiming {
+        ScanTiming {
+            line_rate: self.line_rate(),
+            total_lines: Some(self.vtotal),
+            pixel_clock: Some(self.pixel_clock),
+        }
+    }
+
+    /// Parse an X11 modeline, such as the ones printed by `cvt` or `gtf`:
+    ///
+    /// `Modeline "1366x768_60.00" 72.40 1366 1414 1446 1526 768 771 777 790 -hsync -vsync`
+    ///
+    /// Both the `Modeline` keyword and the mode name are optional.
+    /// Flags after the vertical timings are ignored.
+    pub fn from_modeline(modeline: &str) -> Result<Self, String> {
+        let mut words = modeline.split_whitespace().peekable();
+        // skip keyword and mode name, if present
+        words.next_if(|w| w.eq_ignore_ascii_case("modeline"));
+        words.next_if(|w| w.starts_with('"'));
+        let numbers: Vec<&str> = words.take(9).collect();
+        if numbers.len() < 9 {
+            return Err(format!(
+                    "modeline '{}' should have a pixel clock and 8 timings",
+                    modeline));
+        }
+        let pixel_clock_mhz = parse_number::<f64>(numbers[0], "pixel clock")?;
+        let timings = numbers[1..].iter()
+            .map(|n| parse_number::<u32>(n, "modeline timing"))
+            .collect::<Result<Vec<u32>, String>>()?;
+        // the order is: display, sync start, sync end, total;
+        // first horizontal, then vertical
+        DisplayTiming {
+            pixel_clock: pixel_clock_mhz * 1e6,
+            hdisplay: timings[0],
+            htotal: timings[3],
+            vdisplay: timings[4],
+            vtotal: timings[7],
+        }.validated()
+    }
+
+    /// Parse a comma-separated list of the timings we actually care about:
+    /// `CLOCK_MHZ,HDISPLAY,HTOTAL,VDISPLAY,VTOTAL`.
+    pub fn from_short_timing(timing: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = timing.split(',').map(str::trim).collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                    "timing '{}' should be CLOCK_MHZ,HDISPLAY,HTOTAL,VDISPLAY,VTOTAL",
+                    timing));
+        }
+        let pixel_clock_mhz = parse_number::<f64>(fields[0], "pixel clock")?;
+        DisplayTiming {
+            pixel_clock: pixel_clock_mhz * 1e6,
+            hdisplay: parse_number(fields[1], "hdisplay")?,
+            htotal: parse_number(fields[2], "htotal")?,
+            vdisplay: parse_number(fields[3], "vdisplay")?,
+            vtotal: parse_number(fields[4], "vtotal")?,
+        }.validated()
+    }
+
+    fn validated(self) -> Result<Self, String> {
+        if self.pixel_clock <= 0.0 {
+            Err(format!("pixel clock must be positive, got {}Hz", self.pixel_clock))
+        } else if self.htotal < self.hdisplay || self.htotal == 0 {
+            Err(format!("htotal ({}) must be at least hdisplay ({})",
+                        self.htotal, self.hdisplay))
+        } else if self.vtotal < self.vdisplay || self.vtotal == 0 {
+            Err(format!("vtotal ({}) must be at least vdisplay ({})",
+                        self.vtotal, self.vdisplay))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(word: &str, what: &str) -> Result<T, String>
+where T::Err: std::fmt::Display {
+    word.parse::<T>()
+        .map_err(|e| format!("failed to parse {} '{}': {}", what, word, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED_1366X768: DisplayTiming = DisplayTiming {
+        pixel_clock: 72_400_000.0,
+        hdisplay: 1366,
+        htotal: 1526,
+        vdisplay: 768,
+        vtotal: 790,
+    };
+
+    #[test]
+    fn parse_full_modeline() {
+        let modeline = r#"Modeline "1366x768_60.00"  72.40  1366 1414 1446 1526  768 771 777 790 -hsync -vsync"#;
+        let timing = DisplayTiming::from_modeline(modeline).unwrap();
+        assert_eq!(timing, EXPECTED_1366X768);
+        let refresh_rate = timing.line_rate() / (timing.vtotal as f64);
+        assert!((refresh_rate - 60.06).abs() < 0.01);
+    }
+
+    #[test]
+    fn parse_bare_modeline() {
+        let modeline = "72.40 1366 1414 1446 1526 768 771 777 790";
+        let timing = DisplayTiming::from_modeline(modeline).unwrap();
+        assert_eq!(timing, EXPECTED_1366X768);
+    }
+
+    #[test]
+    fn parse_short_timing() {
+        let timing = DisplayTiming::from_short_timing("72.4,1366,1526,768,790").unwrap();
+        assert_eq!(timing, EXPECTED_1366X768);
+        assert_eq!(timing.scan_timing(), ScanTiming {
+            line_rate: 72_400_000.0 / 1526.0,
+            total_lines: Some(790),
+            pixel_clock: Some(72_400_000.0),
+        });
+    }
+
+    #[test]
+    fn reject_bad_timings() {
+        assert!(DisplayTiming::from_modeline("72.40 1366 1414 1446").is_err());
+        assert!(DisplayTiming::from_modeline("fast 1366 1414 1446 1526 768 771 777 790").is_err());
+        assert!(DisplayTiming::from_short_timing("72.4,1366,1526,768").is_err());
+        // vtotal smaller than vdisplay
+        assert!(DisplayTiming::from_short_timing("72.4,1366,1526,768,700").is_err());
+    }
+}
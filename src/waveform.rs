@@ -0,0 +1,248 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+// The shapes notes can be played with.
+//
+// A wavetable file holds a single cycle of a wave, as numbers separated by
+// whitespace or commas, in any scale (they're normalized so the peak is 1).
+// Lines starting with '#' are ignored. For example, a rough sawtooth:
+//
+//     # one cycle
+//     0 0.25 0.5 0.75 1 -1 -0.75 -0.5 -0.25
+
+use std::{
+    f64::consts,
+    fs,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+// Number of steps per cycle in the cosine table. Interpolating linearly
+// between them is off by less than 2e-8, i.e. less than 1/100000 of a gray
+// level, while being several times faster than calling `cos`.
+const COS_TABLE_STEPS: usize = 16384;
+
+/// The shape of a periodic wave, with a period of 1.
+/// Values go from -1 (black rows) to 1 (white rows).
+pub trait WaveShape: Send + Sync {
+    /// Identifies the shape, so frames rendered with it can be cached.
+    fn name(&self) -> String;
+
+    /// Value at the given phase, in cycles (which may be more than 1).
+    /// `duty` is the voice's duty cycle, for shapes that have one.
+    fn value(&self, phase: f64, duty: f64) -> f64;
+
+    /// Average value over the phases from `start` to `end`,
+    /// used to antialias rows. By default, just the value at `start`.
+    fn average(&self, start: f64, _end: f64, duty: f64) -> f64 {
+        self.value(start, duty)
+    }
+
+    /// Whether the shape only takes the values -1 and 1,
+    /// in which case single notes need no dithering (unless antialiased).
+    fn is_two_level(&self) -> bool {
+        false
+    }
+}
+
+pub type Waveform = Arc<dyn WaveShape>;
+
+/// Parse `square` (or `pulse`), `cosine`, `triangle`, `sawtooth`,
+/// or else the path to a wavetable file.
+pub fn parse_waveform(waveform: &str) -> Result<Waveform, String> {
+    match waveform {
+        "square" | "pulse" => Ok(Arc::new(Square)),
+        "cosine" => Ok(Arc::new(Cosine)),
+        "triangle" => Ok(Arc::new(Triangle)),
+        "sawtooth" => Ok(Arc::new(Sawtooth)),
+        path => Ok(Arc::new(Wavetable::load(Path::new(path))?)),
+    }
+}
+
+/// A square wave, or a pulse wave if the duty cycle isn't 50%:
+/// high during the first `duty` fraction of each cycle.
+/// With a 50% duty cycle, it's high exactly when sin(2pi*phase) >= 0.
+pub struct Square;
+
+impl WaveShape for Square {
+    fn name(&self) -> String {
+        "square".to_string()
+    }
+
+    fn value(&self, phase: f64, duty: f64) -> f64 {
+        // since the phase is never negative,
+        // `fract` gives us how far into the cycle we are
+        if phase.fract() < duty { 1.0 } else { -1.0 }
+    }
+
+    fn average(&self, start: f64, end: f64, duty: f64) -> f64 {
+        // how long the wave was high from phase 0 up to the given phase
+        let high_until = |phase: f64| phase.floor() * duty + phase.fract().min(duty);
+        let high_fraction = (high_until(end) - high_until(start)) / (end - start);
+        2.0 * high_fraction - 1.0
+    }
+
+    fn is_two_level(&self) -> bool {
+        true
+    }
+}
+
+pub struct Cosine;
+
+impl WaveShape for Cosine {
+    fn name(&self) -> String {
+        "cosine".to_string()
+    }
+
+    fn value(&self, phase: f64, _duty: f64) -> f64 {
+        let position = phase.fract() * (COS_TABLE_STEPS as f64);
+        let index = position as usize;
+        let table = cos_table();
+        table[index] + (table[index + 1] - table[index]) * position.fract()
+    }
+}
+
+// The cosine at each step of a cycle, including both ends.
+fn cos_table() -> &'static [f64] {
+    static TABLE: OnceLock<Vec<f64>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..=COS_TABLE_STEPS)
+            // note: TAU = 2 * PI
+            .map(|step| (consts::TAU * (step as f64) / (COS_TABLE_STEPS as f64)).cos())
+            .collect()
+    })
+}
+
+/// Goes up from -1 to 1 during the first half of each cycle,
+/// and back down during the second half.
+pub struct Triangle;
+
+impl WaveShape for Triangle {
+    fn name(&self) -> String {
+        "triangle".to_string()
+    }
+
+    fn value(&self, phase: f64, _duty: f64) -> f64 {
+        1.0 - 4.0 * (phase.fract() - 0.5).abs()
+    }
+}
+
+/// Goes up from -1 to 1 during each cycle, then drops back to -1.
+pub struct Sawtooth;
+
+impl WaveShape for Sawtooth {
+    fn name(&self) -> String {
+        "sawtooth".to_string()
+    }
+
+    fn value(&self, phase: f64, _duty: f64) -> f64 {
+        2.0 * phase.fract() - 1.0
+    }
+
+    fn average(&self, start: f64, end: f64, _duty: f64) -> f64 {
+        // the integral of each whole cycle is 0
+        let integral_until = |phase: f64| phase.fract() * phase.fract() - phase.fract();
+        (integral_until(end) - integral_until(start)) / (end - start)
+    }
+}
+
+/// A single cycle given as evenly spaced samples,
+/// linearly interpolated in between.
+pub struct Wavetable {
+    name: String,
+    samples: Vec<f64>,
+}
+
+impl Wavetable {
+    /// `name` identifies the table, e.g. the file it came from.
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let samples = text.lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|word| !word.is_empty())
+            .map(|word| word.parse::<f64>()
+                 .map_err(|e| format!("bad wavetable sample '{}': {}", word, e)))
+            .collect::<Result<Vec<f64>, String>>()?;
+        if samples.len() < 2 {
+            return Err("a wavetable needs at least two samples".to_string());
+        }
+        let peak = samples.iter().fold(0.0, |peak: f64, sample| peak.max(sample.abs()));
+        if peak == 0.0 || !peak.is_finite() {
+            return Err("wavetable samples can't all be zero".to_string());
+        }
+        Ok(Wavetable {
+            name: name.to_string(),
+            samples: samples.iter().map(|sample| sample / peak).collect(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read wavetable file {}: {}", path.display(), e))?;
+        Self::parse(&path.display().to_string(), &text)
+            .map_err(|e| format!("invalid wavetable file {}: {}", path.display(), e))
+    }
+}
+
+impl WaveShape for Wavetable {
+    fn name(&self) -> String {
+        format!("wavetable {}", self.name)
+    }
+
+    fn value(&self, phase: f64, _duty: f64) -> f64 {
+        let position = phase.fract() * (self.samples.len() as f64);
+        let index = position as usize;
+        let next_index = (index + 1) % self.samples.len();
+        let weight = position.fract();
+        self.samples[index] * (1.0 - weight) + self.samples[next_index] * weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(shape: &dyn WaveShape, phases: &[f64]) -> Vec<f64> {
+        phases.iter().map(|phase| shape.value(*phase, 0.5)).collect()
+    }
+
+    #[test]
+    fn basic_shapes() {
+        let phases = [0.0, 0.25, 0.5, 0.75, 1.0];
+        assert_eq!(values(&Square, &phases), vec![1.0, 1.0, -1.0, -1.0, 1.0]);
+        assert_eq!(values(&Triangle, &phases), vec![-1.0, 0.0, 1.0, 0.0, -1.0]);
+        assert_eq!(values(&Sawtooth, &phases), vec![-1.0, -0.5, 0.0, 0.5, -1.0]);
+        assert_eq!(Square.value(0.3, 0.25), -1.0);
+    }
+
+    #[test]
+    fn cosine_table_is_accurate() {
+        for step in 0..100_000 {
+            let phase = (step as f64) * 0.0000731;
+            let error = Cosine.value(phase, 0.5) - (consts::TAU * phase).cos();
+            assert!(error.abs() < 2e-8, "phase = {}, error = {}", phase, error);
+        }
+    }
+
+    #[test]
+    fn averages() {
+        // high for the first half of [0, 1)
+        assert_eq!(Square.average(0.25, 0.75, 0.5), 0.0);
+        assert_eq!(Sawtooth.average(0.0, 1.0, 0.5), 0.0);
+        assert_eq!(Sawtooth.average(0.5, 1.0, 0.5), 0.5);
+    }
+
+    #[test]
+    fn wavetable_is_interpolated_and_normalized() {
+        let table = Wavetable::parse("test", "# a triangle\n0, 2\n0 -2").unwrap();
+        let phases = [0.0, 0.125, 0.25, 0.5, 0.75, 0.875];
+        assert_eq!(values(&table, &phases), vec![0.0, 0.5, 1.0, 0.0, -1.0, -0.5]);
+        assert!(Wavetable::parse("test", "1").is_err());
+        assert!(Wavetable::parse("test", "0 0 0").is_err());
+        assert!(Wavetable::parse("test", "0 loud").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (C) 2025 Luana Martins Barbosa
+//
+// This file is part of tempest-lcd.
+// tempest-lcd is free software, released under the
+// GNU Public License, version 2 only.
+// See COPYING.txt.
+
+use std::{
+    sync::{Arc, Mutex, mpsc},
+    thread::{self, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads that are started once and kept waiting for jobs,
+/// since starting new ones for every frame takes a while.
+pub struct WorkerPool {
+    // `None` once the pool is being dropped, which stops the workers
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// A pool of `threads` threads. With a single thread (or none),
+    /// no thread is started, and jobs run on the calling thread instead.
+    pub fn new(threads: usize) -> Self {
+        if threads <= 1 {
+            return WorkerPool { jobs: None, workers: Vec::new() };
+        }
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    // the lock is released before running the job,
+                    // so the other workers can take the next ones
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        // the pool was dropped
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        WorkerPool { jobs: Some(sender), workers }
+    }
+
+    /// How many jobs can run at the same time.
+    pub fn threads(&self) -> usize {
+        self.workers.len().max(1)
+    }
+
+    /// Run the given jobs, spread among the threads,
+    /// and return what each of them returned, in the same order.
+    pub fn run<T, F>(&self, jobs: Vec<F>) -> Vec<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let Some(sender) = &self.jobs else {
+            return jobs.into_iter().map(|job| job()).collect();
+        };
+        let job_count = jobs.len();
+        let (result_sender, results) = mpsc::channel();
+        for (index, job) in jobs.into_iter().enumerate() {
+            let result_sender = result_sender.clone();
+            sender.send(Box::new(move || {
+                // only fails if `run` already panicked
+                let _ = result_sender.send((index, job()));
+            })).expect("worker threads stopped");
+        }
+        // otherwise, a job that panicked would leave us waiting forever
+        drop(result_sender);
+        let mut outputs: Vec<Option<T>> = (0..job_count).map(|_| None).collect();
+        for _ in 0..job_count {
+            let (index, output) = results.recv().expect("a job panicked in a worker thread");
+            outputs[index] = Some(output);
+        }
+        outputs.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // closing the channel makes every worker stop once it's idle
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_keep_the_jobs_order() {
+        for threads in [1, 3] {
+            let pool = WorkerPool::new(threads);
+            assert_eq!(pool.threads(), threads);
+            // run twice, to check the threads are still there
+            for _ in 0..2 {
+                let jobs: Vec<_> = (0..10).map(|index| move || index * index).collect();
+                assert_eq!(pool.run(jobs), (0..10).map(|index| index * index).collect::<Vec<_>>());
+            }
+        }
+    }
+}